@@ -1,5 +1,4 @@
-use chrono;
-use chrono::prelude::*;
+use crate::train_time::TrainTime;
 
 #[derive(PartialEq)]
 pub enum ClockType {
@@ -17,34 +16,38 @@ impl Clocks {
         clock_type: ClockType,
         clock_brightness: u8,
         address: Option<u8>,
+        tm1637_pins: (u8, u8),
     ) -> Result<Clocks, Box<dyn std::error::Error>> {
         match clock_type {
             ClockType::TM1637 => {
-                let clock_tm1637 = crate::tm1637::ClockDisplay::new(clock_brightness)?;
-                return Ok(Clocks {
+                let (clock_pin, data_pin) = tm1637_pins;
+                let clock_tm1637 =
+                    crate::tm1637::ClockDisplay::new(clock_brightness, clock_pin, data_pin)?;
+                Ok(Clocks {
                     display_ht16k33: None,
                     display_tm1637: Some(clock_tm1637),
-                });
+                })
             }
             ClockType::HT16K33 => {
                 let clock_ht16k33 =
                     crate::ht16k33::ClockDisplay::new(address.unwrap(), clock_brightness)?;
-                return Ok(Clocks {
+                Ok(Clocks {
                     display_ht16k33: Some(clock_ht16k33),
                     display_tm1637: None,
-                });
+                })
             }
-        };
+        }
     }
     pub fn display_time_until(
         &mut self,
-        train_times_list: &[chrono::DateTime<Local>],
-        minimum_display_min: &i64,
+        train_times_list: &[TrainTime],
+        walk_min: &i64,
+        late_threshold_min: &i64,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(display) = &mut self.display_ht16k33 {
-            display.display_time_until(train_times_list, minimum_display_min)?
+            display.display_time_until(train_times_list, walk_min, late_threshold_min)?
         } else if let Some(display) = &mut self.display_tm1637 {
-            display.display_time_until(train_times_list, minimum_display_min)?
+            display.display_time_until(train_times_list, walk_min, late_threshold_min)?
         }
         Ok(())
     }
@@ -0,0 +1,650 @@
+use chrono::{NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Settings for the countdown, from the config file defaults, a profile or the command line.
+/// Anything left out falls back to the next source, ending with `Settings::builtin`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub station: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_number: Option<Vec<String>>,
+    /// Destination station, only counting down the trains stopping there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commuter_rail: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subway_line: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ferry_line: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bus: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
+    /// Minutes it takes to walk to the station.  The clock counts down to when to leave, and
+    /// trains that can no longer be caught are skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub walk_min: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub late_threshold: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_full: Option<bool>,
+    /// arrival, departure or best
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_type: Option<String>,
+    /// HT16K33 or TM1637
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_brightness: Option<u8>,
+    /// I2C address of the HT16K33 clock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_address: Option<u8>,
+    /// I2C address of the ssd1306 screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screen_address: Option<u16>,
+    /// GPIO pins of the TM1637 clock and data lines
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tm1637_clock_pin: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tm1637_data_pin: Option<u8>,
+    /// GPIO pin of the shutdown button
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_pin: Option<u8>,
+    /// Shortest and longest waits between fetching the train times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_poll_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_poll_secs: Option<u64>,
+}
+
+/// The config file, with default settings shared by every profile
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile used when none is given with --profile
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub defaults: Settings,
+    /// Profile name -> settings
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Settings>,
+    /// Times of the week each profile is switched to while running.  Outside of them the default
+    /// profile is used
+    #[serde(default)]
+    pub schedule: Vec<ScheduledProfile>,
+    /// The schedule with its days and times parsed, filled in when the config is parsed
+    #[serde(skip)]
+    windows: Vec<ScheduleWindow>,
+}
+
+/// A profile used on some days between two times, ie weekdays from 06:00 to 10:00
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledProfile {
+    pub profile: String,
+    /// Days of the week, ie ["mon", "tue"], or "weekdays" or "weekends".  Every day when left out
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Agency time the profile starts and stops being used, ie "06:00" and "10:00".  A window
+    /// ending before it starts runs past midnight, ie "22:00" to "02:00", and its days are the
+    /// days it starts on
+    pub start: String,
+    pub end: String,
+}
+
+/// A scheduled profile's days and times, parsed once when the config is loaded
+#[derive(Clone, Debug, PartialEq)]
+struct ScheduleWindow {
+    profile: String,
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+/// Settings only read from the default profile and the defaults, as the displays and button are
+/// set up once at the start
+const HARDWARE_SETTINGS: [&str; 9] = [
+    "clock_type",
+    "clock_brightness",
+    "clock_address",
+    "screen_address",
+    "tm1637_clock_pin",
+    "tm1637_data_pin",
+    "shutdown_pin",
+    "min_poll_secs",
+    "max_poll_secs",
+];
+
+/// Default location of the config file, ie ~/.config/mbta_countdown/config.toml
+pub fn default_config_file() -> PathBuf {
+    match dirs::config_dir() {
+        Some(config_dir) => config_dir.join("mbta_countdown").join("config.toml"),
+        None => PathBuf::from("mbta_countdown.toml"),
+    }
+}
+
+impl Config {
+    /// Reads and validates the config file
+    pub fn load(config_file: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(config_file)
+            .map_err(|err| format!("Could not read {}: {}", config_file.display(), err))?;
+        Config::parse(&text)
+            .map_err(|err| format!("Invalid config {}: {}", config_file.display(), err).into())
+    }
+
+    /// Parses and validates the text of a config file
+    pub fn parse(text: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config: Config = toml::from_str(text)?;
+        config.defaults.validate("defaults")?;
+        for (name, profile) in &config.profiles {
+            profile.validate(&format!("profile {}", name))?;
+        }
+        if let Some(default_profile) = &config.default_profile {
+            config.profile(default_profile)?;
+        }
+        let mut windows = Vec::new();
+        for scheduled in &config.schedule {
+            config.profile(&scheduled.profile)?;
+            // the displays are set up from the default profile, so they cannot be switched
+            if config.default_profile.as_ref() != Some(&scheduled.profile) {
+                let hardware = config.profiles[&scheduled.profile].hardware_settings();
+                if !hardware.is_empty() {
+                    return Err(format!(
+                        "Scheduled profile {} sets {}, which can only be set in the default profile or [defaults]",
+                        scheduled.profile,
+                        hardware.join(", ")
+                    )
+                    .into());
+                }
+            }
+            windows.push(scheduled.window()?);
+        }
+        config.windows = windows;
+        Ok(config)
+    }
+
+    /// Name of the profile scheduled for the day and time, if any.  The first matching window in
+    /// the schedule is used when they overlap
+    pub fn scheduled_profile(&self, weekday: Weekday, time: NaiveTime) -> Option<&str> {
+        self.windows
+            .iter()
+            .find(|window| window.is_active(weekday, time))
+            .map(|window| window.profile.as_str())
+    }
+
+    /// The profile's settings, falling back to the config defaults
+    pub fn profile(&self, name: &str) -> Result<Settings, Box<dyn std::error::Error>> {
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            format!(
+                "Unknown profile {}.  Profiles in the config: {}",
+                name,
+                self.profiles
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        })?;
+        Ok(profile.clone().or(&self.defaults))
+    }
+}
+
+impl ScheduledProfile {
+    /// The days and times the profile is used
+    fn window(&self) -> Result<ScheduleWindow, Box<dyn std::error::Error>> {
+        let weekdays = [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ];
+        let weekends = [Weekday::Sat, Weekday::Sun];
+        let mut days = Vec::new();
+        if self.days.is_empty() {
+            days.extend(weekdays);
+            days.extend(weekends);
+        }
+        for day in &self.days {
+            match day.to_lowercase().as_str() {
+                "weekdays" => days.extend(weekdays),
+                "weekends" => days.extend(weekends),
+                _ => days.push(day.parse::<Weekday>().map_err(|_| {
+                    format!(
+                        "Invalid days in schedule for profile {}: {} is not a day, weekdays or weekends",
+                        self.profile, day
+                    )
+                })?),
+            }
+        }
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| {
+                format!(
+                    "Invalid time in schedule for profile {}: {} is not HH:MM",
+                    self.profile, time
+                )
+            })
+        };
+        let (start, end) = (time(&self.start)?, time(&self.end)?);
+        if start == end {
+            return Err(format!(
+                "Invalid schedule for profile {}: start and end are both {}.  Leave out the schedule to use the profile all day",
+                self.profile, self.start
+            )
+            .into());
+        }
+        Ok(ScheduleWindow {
+            profile: self.profile.clone(),
+            days,
+            start,
+            end,
+        })
+    }
+}
+
+impl ScheduleWindow {
+    /// Whether the profile is used at this time on this day.  The end time is not included.  A
+    /// window running past midnight is used after midnight on the days after its days
+    fn is_active(&self, weekday: Weekday, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            return self.days.contains(&weekday) && self.start <= time && time < self.end;
+        }
+        (self.days.contains(&weekday) && self.start <= time)
+            || (self.days.contains(&weekday.pred()) && time < self.end)
+    }
+}
+
+/// Adds the profile to the end of the config file, creating the file if needed, so the rest of
+/// the file including its comments is kept
+pub fn append_profile(
+    config_file: &Path,
+    name: &str,
+    profile: &Settings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config_file.exists() && Config::load(config_file)?.profiles.contains_key(name) {
+        return Err(format!("Profile {} is already in {}", name, config_file.display()).into());
+    }
+    if let Some(config_dir) = config_file.parent() {
+        fs::create_dir_all(config_dir)?;
+    }
+    // only the profile's table, as the defaults may already be in the file
+    let mut profiles = BTreeMap::new();
+    profiles.insert(name, profile);
+    let mut document = BTreeMap::new();
+    document.insert("profiles", profiles);
+    let text = toml::to_string(&document)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config_file)?;
+    writeln!(file, "\n{}", text.trim())?;
+    Ok(())
+}
+
+/// Fills in each setting left out of `$settings` from `$fallback`
+macro_rules! fallback {
+    ($settings:ident, $fallback:ident, $($field:ident),*) => {
+        Settings {
+            $($field: $settings.$field.or_else(|| $fallback.$field.clone()),)*
+        }
+    };
+}
+
+impl Settings {
+    /// Values used when neither the config file nor the command line set them
+    pub fn builtin() -> Settings {
+        Settings {
+            walk_min: Some(0),
+            late_threshold: Some(5),
+            skip_full: Some(false),
+            time_type: Some("best".to_string()),
+            clock_type: Some("HT16K33".to_string()),
+            clock_brightness: Some(7),
+            clock_address: Some(0x70),
+            screen_address: Some(0x3c),
+            tm1637_clock_pin: Some(22),
+            tm1637_data_pin: Some(27),
+            shutdown_pin: Some(13),
+            min_poll_secs: Some(15),
+            max_poll_secs: Some(600),
+            ..Settings::default()
+        }
+    }
+
+    /// These settings, with any left out taken from the fallback.  The lines and the stations
+    /// are each taken as a group, so any line set here replaces all of the fallback's lines
+    /// rather than adding to them, and the same for stations and stop numbers
+    pub fn or(self, fallback: &Settings) -> Settings {
+        let mut fallback = fallback.clone();
+        if self.commuter_rail.is_some()
+            || self.subway_line.is_some()
+            || self.ferry_line.is_some()
+            || self.bus.is_some()
+        {
+            fallback.commuter_rail = None;
+            fallback.subway_line = None;
+            fallback.ferry_line = None;
+            fallback.bus = None;
+        }
+        if self.station.is_some() || self.stop_number.is_some() {
+            fallback.station = None;
+            fallback.stop_number = None;
+        }
+        let settings = self;
+        fallback!(
+            settings,
+            fallback,
+            station,
+            stop_number,
+            to,
+            commuter_rail,
+            subway_line,
+            ferry_line,
+            bus,
+            direction,
+            walk_min,
+            late_threshold,
+            skip_full,
+            time_type,
+            clock_type,
+            clock_brightness,
+            clock_address,
+            screen_address,
+            tm1637_clock_pin,
+            tm1637_data_pin,
+            shutdown_pin,
+            min_poll_secs,
+            max_poll_secs
+        )
+    }
+
+    /// Names of the hardware settings that are set
+    fn hardware_settings(&self) -> Vec<&'static str> {
+        let set = [
+            self.clock_type.is_some(),
+            self.clock_brightness.is_some(),
+            self.clock_address.is_some(),
+            self.screen_address.is_some(),
+            self.tm1637_clock_pin.is_some(),
+            self.tm1637_data_pin.is_some(),
+            self.shutdown_pin.is_some(),
+            self.min_poll_secs.is_some(),
+            self.max_poll_secs.is_some(),
+        ];
+        HARDWARE_SETTINGS
+            .iter()
+            .zip(set.iter())
+            .filter(|(_, set)| **set)
+            .map(|(setting, _)| *setting)
+            .collect()
+    }
+
+    /// Checks the values that are set, naming where they came from in the errors, ie "profile
+    /// work" or "command line"
+    pub fn validate(&self, source: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let invalid = |setting: &str, problem: String| -> Result<(), Box<dyn std::error::Error>> {
+            Err(format!("Invalid {} in {}: {}", setting, source, problem).into())
+        };
+        if let Some(time_type) = &self.time_type {
+            if !["arrival", "departure", "best"].contains(&time_type.as_str()) {
+                invalid(
+                    "time_type",
+                    format!("{} is not arrival, departure or best", time_type),
+                )?;
+            }
+        }
+        if let Some(clock_type) = &self.clock_type {
+            if !["HT16K33", "TM1637"].contains(&clock_type.as_str()) {
+                invalid(
+                    "clock_type",
+                    format!("{} is not HT16K33 or TM1637", clock_type),
+                )?;
+            }
+        }
+        // the brightness limit depends on the clock, which may come from elsewhere
+        if let Some(clock_brightness) = self.clock_brightness {
+            let limit = match self.clock_type.as_deref() {
+                Some("TM1637") => 7,
+                _ => 9,
+            };
+            if clock_brightness > limit {
+                invalid(
+                    "clock_brightness",
+                    format!(
+                        "{} is above the clock's limit of {}",
+                        clock_brightness, limit
+                    ),
+                )?;
+            }
+        }
+        // stop numbers are the numbers on the bus stop signs
+        for stop_number in self.stop_number.iter().flatten() {
+            if stop_number.is_empty() || !stop_number.chars().all(|c| c.is_ascii_digit()) {
+                invalid("stop_number", format!("{} is not a number", stop_number))?;
+            }
+        }
+        for (setting, minutes) in &[
+            ("walk_min", self.walk_min),
+            ("late_threshold", self.late_threshold),
+        ] {
+            if minutes.is_some_and(|minutes| minutes < 0) {
+                invalid(setting, "minutes cannot be negative".to_string())?;
+            }
+        }
+        // the Raspberry Pi has GPIO pins 0 to 27
+        for (setting, pin) in &[
+            ("tm1637_clock_pin", self.tm1637_clock_pin),
+            ("tm1637_data_pin", self.tm1637_data_pin),
+            ("shutdown_pin", self.shutdown_pin),
+        ] {
+            if let Some(pin) = pin.filter(|pin| *pin > 27) {
+                invalid(setting, format!("GPIO pin {} is above 27", pin))?;
+            }
+        }
+        if self.min_poll_secs == Some(0) {
+            invalid("min_poll_secs", "must be at least 1 second".to_string())?;
+        }
+        if let (Some(min_poll_secs), Some(max_poll_secs)) = (self.min_poll_secs, self.max_poll_secs)
+        {
+            if min_poll_secs > max_poll_secs {
+                invalid(
+                    "max_poll_secs",
+                    format!("{} is below min_poll_secs {}", max_poll_secs, min_poll_secs),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default_profile = "morning"
+
+[defaults]
+clock_type = "TM1637"
+walk_min = 8
+
+[profiles.morning]
+station = ["Needham Heights"]
+commuter_rail = ["Needham"]
+direction = "inbound"
+
+[profiles.evening]
+station = ["South Station"]
+commuter_rail = ["Needham"]
+direction = "outbound"
+walk_min = 12
+"#;
+
+    #[test]
+    fn profiles_fall_back_to_defaults() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let morning = config.profile("morning").unwrap();
+        assert_eq!(morning.walk_min, Some(8));
+        assert_eq!(morning.clock_type.as_deref(), Some("TM1637"));
+        let evening = config.profile("evening").unwrap().or(&Settings::builtin());
+        assert_eq!(evening.walk_min, Some(12));
+        assert_eq!(evening.screen_address, Some(0x3c));
+        assert!(config.profile("weekend").is_err());
+    }
+
+    #[test]
+    fn lines_and_stations_replace_the_profile_ones() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let morning = config.profile("morning").unwrap();
+        // --profile morning -l Red -s Park_Street
+        let cli = Settings {
+            subway_line: Some(vec!["Red".to_string()]),
+            station: Some(vec!["Park Street".to_string()]),
+            ..Settings::default()
+        };
+        let settings = cli.or(&morning);
+        assert_eq!(settings.subway_line, Some(vec!["Red".to_string()]));
+        assert_eq!(settings.commuter_rail, None);
+        assert_eq!(settings.station, Some(vec!["Park Street".to_string()]));
+        assert_eq!(settings.direction.as_deref(), Some("inbound"));
+        // --profile morning --stop_number 1234 keeps the profile's line
+        let cli = Settings {
+            stop_number: Some(vec!["1234".to_string()]),
+            ..Settings::default()
+        };
+        let settings = cli.or(&morning);
+        assert_eq!(settings.station, None);
+        assert_eq!(settings.stop_number, Some(vec!["1234".to_string()]));
+        assert_eq!(settings.commuter_rail, Some(vec!["Needham".to_string()]));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let settings = Settings {
+            clock_type: Some("TM1637".to_string()),
+            clock_brightness: Some(8),
+            ..Settings::default()
+        };
+        assert!(settings.validate("defaults").is_err());
+        let settings = Settings {
+            min_poll_secs: Some(60),
+            max_poll_secs: Some(30),
+            ..Settings::default()
+        };
+        assert!(settings.validate("defaults").is_err());
+        let settings = Settings {
+            stop_number: Some(vec!["1234".to_string(), "Needham".to_string()]),
+            ..Settings::default()
+        };
+        assert!(settings.validate("defaults").is_err());
+        assert!(Settings::builtin().validate("built in").is_ok());
+        // misspelled settings are not silently ignored
+        assert!(toml::from_str::<Config>("[profiles.work]\nwalk_mins = 5").is_err());
+    }
+
+    #[test]
+    fn scheduled_profiles() {
+        let schedule = r#"
+[[schedule]]
+profile = "morning"
+days = ["weekdays"]
+start = "06:00"
+end = "10:00"
+
+[[schedule]]
+profile = "evening"
+days = ["mon", "Tuesday", "wed", "thu", "fri"]
+start = "15:00"
+end = "20:00"
+"#;
+        let config = Config::parse(&format!("{}{}", CONFIG, schedule)).unwrap();
+        let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        assert_eq!(
+            config.scheduled_profile(Weekday::Tue, time("06:00")),
+            Some("morning")
+        );
+        assert_eq!(
+            config.scheduled_profile(Weekday::Fri, time("19:59")),
+            Some("evening")
+        );
+        // the end time starts the next window
+        assert_eq!(config.scheduled_profile(Weekday::Mon, time("10:00")), None);
+        assert_eq!(config.scheduled_profile(Weekday::Sat, time("07:00")), None);
+
+        // a window past midnight continues into the next day
+        let late = r#"
+[[schedule]]
+profile = "evening"
+days = ["fri"]
+start = "22:00"
+end = "02:00"
+"#;
+        let config = Config::parse(&format!("{}{}", CONFIG, late)).unwrap();
+        assert_eq!(
+            config.scheduled_profile(Weekday::Fri, time("23:30")),
+            Some("evening")
+        );
+        assert_eq!(
+            config.scheduled_profile(Weekday::Sat, time("01:59")),
+            Some("evening")
+        );
+        assert_eq!(config.scheduled_profile(Weekday::Sat, time("02:00")), None);
+        assert_eq!(config.scheduled_profile(Weekday::Fri, time("01:00")), None);
+        assert_eq!(config.scheduled_profile(Weekday::Sat, time("23:00")), None);
+
+        let invalid =
+            |scheduled: &str| Config::parse(&format!("{}{}", CONFIG, scheduled)).map(|_| ());
+        let scheduled = |profile: &str, days: &str, start: &str, end: &str| {
+            format!(
+                "[[schedule]]\nprofile = \"{}\"\ndays = [\"{}\"]\nstart = \"{}\"\nend = \"{}\"",
+                profile, days, start, end
+            )
+        };
+        assert!(invalid(&scheduled("morning", "weekdays", "06:00", "10:00")).is_ok());
+        assert!(invalid(&scheduled("weekend", "weekdays", "06:00", "10:00")).is_err());
+        assert!(invalid(&scheduled("morning", "someday", "06:00", "10:00")).is_err());
+        assert!(invalid(&scheduled("morning", "weekdays", "6am", "10:00")).is_err());
+        assert!(invalid(&scheduled("morning", "weekdays", "06:00", "06:00")).is_err());
+        // the displays only take their settings from the default profile
+        let with_hardware = |profile: &str| {
+            CONFIG.replace(
+                &format!("[profiles.{}]\n", profile),
+                &format!("[profiles.{}]\nclock_brightness = 3\n", profile),
+            )
+        };
+        let schedule = scheduled("evening", "weekdays", "15:00", "20:00");
+        assert!(Config::parse(&format!("{}{}", with_hardware("evening"), schedule)).is_err());
+        assert!(Config::parse(&format!("{}{}", with_hardware("morning"), schedule)).is_ok());
+    }
+
+    #[test]
+    fn appended_profiles_load() {
+        let config_file = std::env::temp_dir()
+            .join(format!("mbta_countdown_config_{}", std::process::id()))
+            .join("config.toml");
+        fs::create_dir_all(config_file.parent().unwrap()).unwrap();
+        fs::write(&config_file, CONFIG).unwrap();
+        let home = Settings {
+            station: Some(vec!["Park_Street".to_string()]),
+            subway_line: Some(vec!["Red".to_string(), "Green-B".to_string()]),
+            direction: Some("inbound".to_string()),
+            ..Settings::default()
+        };
+        append_profile(&config_file, "home", &home).unwrap();
+        // the profile names are unique
+        assert!(append_profile(&config_file, "home", &home).is_err());
+        let config = Config::load(&config_file).unwrap();
+        assert_eq!(config.profiles["home"], home);
+        assert_eq!(config.default_profile.as_deref(), Some("morning"));
+        fs::remove_dir_all(config_file.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,1016 @@
+use crate::train_time::{filter_destination, TimeType, TrainTime};
+use crate::transit_provider::{Alert, VehiclePosition};
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use prost::Message;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+/// MBTA GTFS-Realtime trip updates feed
+pub const MBTA_TRIP_UPDATES_URL: &str = "https://cdn.mbta.com/realtime/TripUpdates.pb";
+/// MBTA GTFS-Realtime service alerts feed
+pub const MBTA_ALERTS_URL: &str = "https://cdn.mbta.com/realtime/Alerts.pb";
+/// MBTA GTFS-Realtime vehicle positions feed
+pub const MBTA_VEHICLE_POSITIONS_URL: &str = "https://cdn.mbta.com/realtime/VehiclePositions.pb";
+
+// The parts of gtfs-realtime.proto needed for departures, vehicles and alerts.  Field numbers match the
+// specification at https://gtfs.org/realtime/reference/ and any fields not listed are skipped
+// when decoding
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedMessage {
+    #[prost(message, repeated, tag = "2")]
+    entity: Vec<FeedEntity>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedEntity {
+    #[prost(bool, optional, tag = "2")]
+    is_deleted: Option<bool>,
+    #[prost(message, optional, tag = "3")]
+    trip_update: Option<TripUpdate>,
+    #[prost(message, optional, tag = "4")]
+    vehicle: Option<GtfsVehiclePosition>,
+    #[prost(message, optional, tag = "5")]
+    alert: Option<GtfsAlert>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripUpdate {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<TripDescriptor>,
+    #[prost(message, repeated, tag = "2")]
+    stop_time_update: Vec<StopTimeUpdate>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    trip_id: Option<String>,
+    /// 0 scheduled, 1 added, 2 unscheduled, 3 canceled, 5 replacement, 7 deleted
+    #[prost(int32, optional, tag = "4")]
+    schedule_relationship: Option<i32>,
+    #[prost(string, optional, tag = "5")]
+    route_id: Option<String>,
+    #[prost(uint32, optional, tag = "6")]
+    direction_id: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeUpdate {
+    #[prost(uint32, optional, tag = "1")]
+    stop_sequence: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    arrival: Option<StopTimeEvent>,
+    #[prost(message, optional, tag = "3")]
+    departure: Option<StopTimeEvent>,
+    #[prost(string, optional, tag = "4")]
+    stop_id: Option<String>,
+    /// 0 scheduled, 1 skipped, 2 no data
+    #[prost(int32, optional, tag = "5")]
+    schedule_relationship: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeEvent {
+    /// Seconds later than scheduled, negative when early
+    #[prost(int32, optional, tag = "1")]
+    delay: Option<i32>,
+    /// POSIX time in seconds
+    #[prost(int64, optional, tag = "2")]
+    time: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GtfsVehiclePosition {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<TripDescriptor>,
+    #[prost(uint32, optional, tag = "3")]
+    current_stop_sequence: Option<u32>,
+    /// 0 incoming at, 1 stopped at, 2 in transit to
+    #[prost(int32, optional, tag = "4")]
+    current_status: Option<i32>,
+    #[prost(string, optional, tag = "7")]
+    stop_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GtfsAlert {
+    #[prost(message, repeated, tag = "1")]
+    active_period: Vec<TimeRange>,
+    #[prost(message, repeated, tag = "5")]
+    informed_entity: Vec<EntitySelector>,
+    #[prost(int32, optional, tag = "7")]
+    effect: Option<i32>,
+    #[prost(message, optional, tag = "10")]
+    header_text: Option<TranslatedString>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeRange {
+    #[prost(uint64, optional, tag = "1")]
+    start: Option<u64>,
+    #[prost(uint64, optional, tag = "2")]
+    end: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EntitySelector {
+    #[prost(string, optional, tag = "2")]
+    route_id: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    stop_id: Option<String>,
+    #[prost(uint32, optional, tag = "6")]
+    direction_id: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TranslatedString {
+    #[prost(message, repeated, tag = "1")]
+    translation: Vec<Translation>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Translation {
+    #[prost(string, optional, tag = "1")]
+    text: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    language: Option<String>,
+}
+
+/// Scheduled time of a trip id at a stop id for the arrival or departure, used for the feeds
+/// that only give the delay
+type ScheduledTime<'a> = dyn Fn(&str, &str, TimeType) -> Option<DateTime<Tz>> + Sync + 'a;
+
+/// Retrieves the predicted times from a GTFS-Realtime trip updates feed, returning the same
+/// train times as `train_time::train_times`.  The feed lists platforms, so `stop_ids` and
+/// `destination_ids` should include the stations along with all of their platforms.  With
+/// destination ids, only the trips stopping there afterwards are kept.
+///
+/// The feed only lists the trips it has updates for, so the `scheduled` trains from the static
+/// schedule are kept unless the feed updates, cancels or skips their trip.  For the stops the feed
+/// only gives the delay of, the time is `scheduled_time` of the trip id, stop id and arrival or
+/// departure plus the delay
+#[allow(clippy::too_many_arguments)]
+pub async fn train_times(
+    trip_updates_url: &str,
+    dir_code: &str,
+    stop_ids: &[String],
+    destination_ids: &[String],
+    route_codes: &[String],
+    time_type: TimeType,
+    scheduled: Vec<TrainTime>,
+    scheduled_time: &ScheduledTime<'_>,
+    timezone: Tz,
+) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>> {
+    let feed_bytes = reqwest::get(trip_updates_url).await?.bytes().await?;
+    feed_train_times(
+        &feed_bytes,
+        dir_code,
+        stop_ids,
+        destination_ids,
+        route_codes,
+        time_type,
+        scheduled,
+        scheduled_time,
+        Utc::now().with_timezone(&timezone),
+    )
+}
+
+/// Decodes the trip updates feed, overlays it on the scheduled trains and keeps the times after
+/// now, as in `train_times`
+#[allow(clippy::too_many_arguments)]
+fn feed_train_times(
+    feed_bytes: &[u8],
+    dir_code: &str,
+    stop_ids: &[String],
+    destination_ids: &[String],
+    route_codes: &[String],
+    time_type: TimeType,
+    scheduled: Vec<TrainTime>,
+    scheduled_time: &ScheduledTime<'_>,
+    now: DateTime<Tz>,
+) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>> {
+    let timezone = now.timezone();
+    let feed = FeedMessage::decode(feed_bytes)?;
+    let direction_id = dir_code.parse::<u32>()?;
+
+    // trip id -> train time, so that each trip is only counted once
+    let mut trip_times: HashMap<String, TrainTime> = HashMap::new();
+    // trip id -> arrival time at the destination
+    let mut arrivals: HashMap<String, DateTime<Tz>> = HashMap::new();
+    // trips canceled, or no longer stopping at the stations or destination
+    let mut removed_trips: HashSet<String> = HashSet::new();
+    for trip_update in feed
+        .entity
+        .iter()
+        .filter(|entity| !entity.is_deleted.unwrap_or(false))
+        .filter_map(|entity| entity.trip_update.as_ref())
+    {
+        let trip = match &trip_update.trip {
+            Some(trip) => trip,
+            None => continue,
+        };
+        // only the trips for the routes and direction.  Some feeds leave out the direction
+        let route = match &trip.route_id {
+            Some(route) if route_codes.contains(route) => route,
+            _ => continue,
+        };
+        if trip.direction_id.is_some_and(|id| id != direction_id) {
+            continue;
+        }
+        let trip_id = match &trip.trip_id {
+            Some(trip_id) => trip_id,
+            None => continue,
+        };
+        if matches!(trip.schedule_relationship, Some(3) | Some(7)) {
+            removed_trips.insert(trip_id.clone());
+            continue;
+        }
+        for stop_time_update in &trip_update.stop_time_update {
+            let stop_id = match &stop_time_update.stop_id {
+                Some(stop_id) => stop_id,
+                None => continue,
+            };
+            // skipped stops and stops without data have no time to display.  Without data, the
+            // scheduled time is kept
+            match stop_time_update.schedule_relationship.unwrap_or(0) {
+                0 => (),
+                1 if stop_ids.contains(stop_id) || destination_ids.contains(stop_id) => {
+                    removed_trips.insert(trip_id.clone());
+                    continue;
+                }
+                _ => continue,
+            }
+            // the predicted and scheduled times of an event.  Without the predicted time, it is
+            // the scheduled time plus the delay
+            let event_times = |event: &Option<StopTimeEvent>, event_type: TimeType| {
+                let event = event.as_ref()?;
+                match (event.time, event.delay) {
+                    (Some(time), delay) => {
+                        let time = Utc
+                            .timestamp_opt(time, 0)
+                            .single()?
+                            .with_timezone(&timezone);
+                        // the scheduled time is only known when the feed gives the delay
+                        let scheduled =
+                            delay.map(|delay| time - chrono::Duration::seconds(delay as i64));
+                        Some((time, scheduled))
+                    }
+                    (None, Some(delay)) => {
+                        let scheduled = scheduled_time(trip_id, stop_id, event_type)?;
+                        Some((
+                            scheduled + chrono::Duration::seconds(delay as i64),
+                            Some(scheduled),
+                        ))
+                    }
+                    (None, None) => None,
+                }
+            };
+            let arrival = event_times(&stop_time_update.arrival, TimeType::Arrival);
+            let departure = event_times(&stop_time_update.departure, TimeType::Departure);
+            if destination_ids.contains(stop_id) {
+                if let Some((time, _)) = arrival.or(departure) {
+                    arrivals.insert(trip_id.clone(), time);
+                }
+            }
+            if !stop_ids.contains(stop_id) {
+                continue;
+            }
+            let event_option = match time_type {
+                TimeType::Arrival => arrival.map(|times| (times, TimeType::Arrival)),
+                TimeType::Departure => departure.map(|times| (times, TimeType::Departure)),
+                TimeType::Best => departure
+                    .map(|times| (times, TimeType::Departure))
+                    .or_else(|| arrival.map(|times| (times, TimeType::Arrival))),
+            };
+            if let Some(((time, scheduled), found_type)) = event_option {
+                // when a trip stops at more than one of the stations, keep the earliest
+                if trip_times
+                    .get(trip_id)
+                    .is_some_and(|train| train.time <= time)
+                {
+                    continue;
+                }
+                trip_times.insert(
+                    trip_id.clone(),
+                    TrainTime {
+                        time,
+                        scheduled,
+                        predicted: Some(time),
+                        route: route.clone(),
+                        trip_id: trip_id.clone(),
+                        destination_arrival: None,
+                        track: None,
+                        stop_sequence: stop_time_update.stop_sequence.map(u64::from),
+                        occupancy: None,
+                        time_type: found_type,
+                        offline: false,
+                    },
+                );
+            }
+        }
+    }
+
+    // the scheduled trains the feed has no times for.  Their scheduled arrival at the destination
+    // is used for the updated trips the feed does not list the destination of
+    let mut scheduled_trains = Vec::new();
+    for train in scheduled {
+        if let Some(arrival) = train.destination_arrival {
+            arrivals.entry(train.trip_id.clone()).or_insert(arrival);
+        }
+        if !trip_times.contains_key(&train.trip_id) && !removed_trips.contains(&train.trip_id) {
+            scheduled_trains.push(train);
+        }
+    }
+
+    // filter out any train time before now, as well as the trips skipping the station
+    let mut all_times = trip_times
+        .into_values()
+        .filter(|train| train.time > now && !removed_trips.contains(&train.trip_id))
+        .collect::<Vec<TrainTime>>();
+    if !destination_ids.is_empty() {
+        all_times = filter_destination(all_times, &arrivals);
+    }
+    all_times.extend(
+        scheduled_trains
+            .into_iter()
+            .filter(|train| train.time > now),
+    );
+    all_times.sort_by_key(|train| train.time);
+    if all_times.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(all_times))
+}
+
+/// Where the vehicle for the train is from a GTFS-Realtime vehicle positions feed, or None before
+/// the vehicle starts the trip.  Mirrors `train_time::vehicle_position`, counting the stops away
+/// from the trip updates feed and naming the stop the vehicle is stopped at with `stop_name`
+pub async fn vehicle_position(
+    vehicle_positions_url: &str,
+    trip_updates_url: &str,
+    train: &TrainTime,
+    stop_name: &(dyn Fn(&str) -> String + Sync),
+) -> Result<Option<VehiclePosition>, Box<dyn Error>> {
+    // the feeds only list stop sequences with the predicted times
+    if train.stop_sequence.is_none() {
+        return Ok(None);
+    }
+    let vehicle_bytes = reqwest::get(vehicle_positions_url).await?.bytes().await?;
+    let trip_update_bytes = reqwest::get(trip_updates_url).await?.bytes().await?;
+    feed_vehicle_position(&vehicle_bytes, &trip_update_bytes, train, stop_name)
+}
+
+/// Decodes the vehicle positions and trip updates feeds to find the vehicle, as in
+/// `vehicle_position`
+fn feed_vehicle_position(
+    vehicle_bytes: &[u8],
+    trip_update_bytes: &[u8],
+    train: &TrainTime,
+    stop_name: &(dyn Fn(&str) -> String + Sync),
+) -> Result<Option<VehiclePosition>, Box<dyn Error>> {
+    let station_sequence = match train.stop_sequence {
+        Some(station_sequence) => station_sequence,
+        None => return Ok(None),
+    };
+    let is_trip = |trip: &Option<TripDescriptor>| {
+        trip.as_ref()
+            .and_then(|trip| trip.trip_id.as_ref())
+            .is_some_and(|trip_id| *trip_id == train.trip_id)
+    };
+    // the vehicle is only listed once it has started the trip
+    let vehicles = FeedMessage::decode(vehicle_bytes)?;
+    let vehicle = match vehicles
+        .entity
+        .iter()
+        .filter(|entity| !entity.is_deleted.unwrap_or(false))
+        .filter_map(|entity| entity.vehicle.as_ref())
+        .find(|vehicle| is_trip(&vehicle.trip))
+    {
+        Some(vehicle) => vehicle,
+        None => return Ok(None),
+    };
+    let current_sequence = match vehicle.current_stop_sequence {
+        Some(current_sequence) => current_sequence as u64,
+        None => return Ok(None),
+    };
+    // the vehicle has already left the station
+    if current_sequence > station_sequence {
+        return Ok(None);
+    }
+    if vehicle.current_status == Some(1) {
+        let stop_name = vehicle
+            .stop_id
+            .as_deref()
+            .map(stop_name)
+            .unwrap_or_default();
+        return Ok(Some(VehiclePosition::StoppedAt(stop_name)));
+    }
+    // stop sequences are not always consecutive, so the stops are counted from the trip update
+    let trip_updates = FeedMessage::decode(trip_update_bytes)?;
+    let stops_away = trip_updates
+        .entity
+        .iter()
+        .filter_map(|entity| entity.trip_update.as_ref())
+        .filter(|trip_update| is_trip(&trip_update.trip))
+        .flat_map(|trip_update| &trip_update.stop_time_update)
+        .filter_map(|stop_time_update| stop_time_update.stop_sequence)
+        .map(u64::from)
+        .filter(|sequence| current_sequence <= *sequence && *sequence < station_sequence)
+        .count();
+    Ok(Some(VehiclePosition::StopsAway(stops_away as u32)))
+}
+
+/// Retrieves the active alerts from a GTFS-Realtime alerts feed that affect any of the routes or
+/// stops
+pub async fn alerts(
+    alerts_url: &str,
+    dir_code: &str,
+    stop_ids: &[String],
+    route_codes: &[String],
+) -> Result<Vec<Alert>, Box<dyn Error>> {
+    let feed_bytes = reqwest::get(alerts_url).await?.bytes().await?;
+    feed_alerts(
+        &feed_bytes,
+        dir_code,
+        stop_ids,
+        route_codes,
+        Utc::now().timestamp() as u64,
+    )
+}
+
+/// Decodes the alerts feed and keeps the alerts active at `now`, in POSIX seconds, as in `alerts`
+fn feed_alerts(
+    feed_bytes: &[u8],
+    dir_code: &str,
+    stop_ids: &[String],
+    route_codes: &[String],
+    now: u64,
+) -> Result<Vec<Alert>, Box<dyn Error>> {
+    let feed = FeedMessage::decode(feed_bytes)?;
+    let direction_id = dir_code.parse::<u32>()?;
+
+    let alerts = feed
+        .entity
+        .iter()
+        .filter(|entity| !entity.is_deleted.unwrap_or(false))
+        .filter_map(|entity| entity.alert.as_ref())
+        // no active periods means the alert is always active
+        .filter(|alert| {
+            alert.active_period.is_empty()
+                || alert.active_period.iter().any(|period| {
+                    period.start.unwrap_or(0) <= now && now <= period.end.unwrap_or(u64::MAX)
+                })
+        })
+        .filter(|alert| {
+            alert.informed_entity.iter().any(|entity| {
+                let route_match = entity
+                    .route_id
+                    .as_ref()
+                    .is_some_and(|route_id| route_codes.contains(route_id))
+                    && entity.direction_id.is_none_or(|id| id == direction_id);
+                let stop_match = entity
+                    .stop_id
+                    .as_ref()
+                    .is_some_and(|stop_id| stop_ids.contains(stop_id));
+                route_match || stop_match
+            })
+        })
+        .filter_map(|alert| {
+            // use the english text if there are translations
+            let translations = &alert.header_text.as_ref()?.translation;
+            let header = translations
+                .iter()
+                .find(|translation| translation.language.as_deref().unwrap_or("en") == "en")
+                .or_else(|| translations.first())?
+                .text
+                .clone()?;
+            Some(Alert {
+                header,
+                effect: effect_name(alert.effect.unwrap_or(8)).to_string(),
+            })
+        })
+        .collect();
+    Ok(alerts)
+}
+
+/// Converts the GTFS-Realtime effect enum to the same names the MBTA V3 API uses
+fn effect_name(effect: i32) -> &'static str {
+    match effect {
+        1 => "NO_SERVICE",
+        2 => "REDUCED_SERVICE",
+        3 => "SIGNIFICANT_DELAYS",
+        4 => "DETOUR",
+        5 => "ADDITIONAL_SERVICE",
+        6 => "MODIFIED_SERVICE",
+        7 => "OTHER_EFFECT",
+        9 => "STOP_MOVED",
+        10 => "NO_EFFECT",
+        11 => "ACCESSIBILITY_ISSUE",
+        _ => "UNKNOWN_EFFECT",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    /// 2021-07-09 17:00 in Boston, in POSIX seconds
+    const NOW: i64 = 1625864400;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn event(minutes: i64, delay: Option<i32>) -> Option<StopTimeEvent> {
+        Some(StopTimeEvent {
+            delay,
+            time: Some(NOW + minutes * 60),
+        })
+    }
+
+    fn stop(stop_id: &str, minutes: i64, delay: Option<i32>) -> StopTimeUpdate {
+        StopTimeUpdate {
+            stop_sequence: None,
+            arrival: event(minutes, delay),
+            departure: event(minutes, delay),
+            stop_id: Some(stop_id.to_string()),
+            schedule_relationship: None,
+        }
+    }
+
+    fn trip_update(
+        trip_id: &str,
+        route_id: &str,
+        direction_id: u32,
+        stops: Vec<StopTimeUpdate>,
+    ) -> FeedEntity {
+        FeedEntity {
+            is_deleted: None,
+            trip_update: Some(TripUpdate {
+                trip: Some(TripDescriptor {
+                    trip_id: Some(trip_id.to_string()),
+                    schedule_relationship: None,
+                    route_id: Some(route_id.to_string()),
+                    direction_id: Some(direction_id),
+                }),
+                stop_time_update: stops,
+            }),
+            vehicle: None,
+            alert: None,
+        }
+    }
+
+    /// Trip ids with their times at the stops, soonest first
+    fn trip_times(
+        entity: Vec<FeedEntity>,
+        stop_ids: &[&str],
+        destination_ids: &[&str],
+    ) -> Vec<(String, String)> {
+        let feed_bytes = FeedMessage { entity }.encode_to_vec();
+        let now = Utc.timestamp_opt(NOW, 0).unwrap().with_timezone(&New_York);
+        feed_train_times(
+            &feed_bytes,
+            "1",
+            &strings(stop_ids),
+            &strings(destination_ids),
+            &strings(&["CR-Needham"]),
+            TimeType::Best,
+            Vec::new(),
+            &|_, _, _| None,
+            now,
+        )
+        .unwrap()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|train| (train.trip_id, train.time.format("%H:%M").to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn trip_updates() {
+        let mut skipped = stop("NHRML-0127-01", 20, None);
+        skipped.schedule_relationship = Some(1);
+        let mut deleted = trip_update(
+            "deleted",
+            "CR-Needham",
+            1,
+            vec![stop("NHRML-0127-01", 6, None)],
+        );
+        deleted.is_deleted = Some(true);
+        let entity = vec![
+            trip_update(
+                "late",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 10, Some(300))],
+            ),
+            trip_update(
+                "early",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-02", 5, None)],
+            ),
+            // already gone, another route, the other direction, a skipped stop and a deleted trip
+            trip_update(
+                "gone",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", -1, None)],
+            ),
+            trip_update(
+                "other route",
+                "CR-Franklin",
+                1,
+                vec![stop("NHRML-0127-01", 7, None)],
+            ),
+            trip_update(
+                "outbound",
+                "CR-Needham",
+                0,
+                vec![stop("NHRML-0127-01", 8, None)],
+            ),
+            trip_update("skipped", "CR-Needham", 1, vec![skipped]),
+            deleted,
+        ];
+        assert_eq!(
+            trip_times(entity, &["NHRML-0127-01", "NHRML-0127-02"], &[]),
+            [
+                ("early".to_string(), "17:05".to_string()),
+                ("late".to_string(), "17:10".to_string())
+            ]
+        );
+
+        // the scheduled time comes from the delay
+        let feed_bytes = FeedMessage {
+            entity: vec![trip_update(
+                "late",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 10, Some(300))],
+            )],
+        }
+        .encode_to_vec();
+        let now = Utc.timestamp_opt(NOW, 0).unwrap().with_timezone(&New_York);
+        let train = feed_train_times(
+            &feed_bytes,
+            "1",
+            &strings(&["NHRML-0127-01"]),
+            &[],
+            &strings(&["CR-Needham"]),
+            TimeType::Best,
+            Vec::new(),
+            &|_, _, _| None,
+            now,
+        )
+        .unwrap()
+        .unwrap()
+        .remove(0);
+        assert_eq!(train.delay(), Some(chrono::Duration::minutes(5)));
+    }
+
+    #[test]
+    fn trip_updates_at_several_stations() {
+        let entity = vec![
+            trip_update(
+                "both stations",
+                "CR-Needham",
+                1,
+                vec![
+                    stop("NHRML-0127-01", 5, None),
+                    stop("NHRML-0152-01", 9, None),
+                ],
+            ),
+            trip_update(
+                "second station",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0152-01", 20, None)],
+            ),
+        ];
+        // a trip stopping at both stations is counted once, at its earliest stop
+        assert_eq!(
+            trip_times(entity, &["NHRML-0152-01", "NHRML-0127-01"], &[]),
+            [
+                ("both stations".to_string(), "17:05".to_string()),
+                ("second station".to_string(), "17:20".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn trip_updates_to_destination() {
+        let entity = vec![
+            trip_update(
+                "to back bay",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 5, None), stop("BBY-01", 30, None)],
+            ),
+            trip_update(
+                "short turn",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 10, None)],
+            ),
+            // another of the destination's platforms
+            trip_update(
+                "other track",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 15, None), stop("BBY-02", 40, None)],
+            ),
+        ];
+        assert_eq!(
+            trip_times(entity, &["NHRML-0127-01"], &["BBY-01", "BBY-02"]),
+            [
+                ("to back bay".to_string(), "17:05".to_string()),
+                ("other track".to_string(), "17:15".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn trip_updates_on_the_schedule() {
+        let now = Utc.timestamp_opt(NOW, 0).unwrap().with_timezone(&New_York);
+        let scheduled_train = |trip_id: &str, minutes: i64| {
+            let time = now + chrono::Duration::minutes(minutes);
+            TrainTime {
+                time,
+                scheduled: Some(time),
+                predicted: None,
+                route: "CR-Needham".to_string(),
+                trip_id: trip_id.to_string(),
+                destination_arrival: None,
+                track: None,
+                stop_sequence: None,
+                occupancy: None,
+                time_type: TimeType::Departure,
+                offline: true,
+            }
+        };
+        let scheduled = vec![
+            scheduled_train("on time", 5),
+            scheduled_train("late", 10),
+            scheduled_train("canceled", 20),
+            scheduled_train("skipped", 25),
+            scheduled_train("gone", 30),
+        ];
+        let mut canceled = trip_update("canceled", "CR-Needham", 1, vec![]);
+        if let Some(trip) = canceled
+            .trip_update
+            .as_mut()
+            .and_then(|trip_update| trip_update.trip.as_mut())
+        {
+            trip.schedule_relationship = Some(3);
+        }
+        let mut skipped = stop("NHRML-0127-01", 25, None);
+        skipped.schedule_relationship = Some(1);
+        let entity = vec![
+            trip_update(
+                "late",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 15, Some(300))],
+            ),
+            canceled,
+            trip_update("skipped", "CR-Needham", 1, vec![skipped]),
+            // the scheduled trip already left early
+            trip_update(
+                "gone",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", -1, None)],
+            ),
+            // not on the schedule
+            trip_update(
+                "added",
+                "CR-Needham",
+                1,
+                vec![stop("NHRML-0127-01", 35, None)],
+            ),
+        ];
+        let train_times = |entity: Vec<FeedEntity>| {
+            feed_train_times(
+                &FeedMessage { entity }.encode_to_vec(),
+                "1",
+                &strings(&["NHRML-0127-01"]),
+                &[],
+                &strings(&["CR-Needham"]),
+                TimeType::Best,
+                scheduled.clone(),
+                &|_, _, _| None,
+                now,
+            )
+            .unwrap()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|train| {
+                (
+                    train.trip_id,
+                    train.time.format("%H:%M").to_string(),
+                    train.offline,
+                )
+            })
+            .collect::<Vec<(String, String, bool)>>()
+        };
+        assert_eq!(
+            train_times(entity),
+            [
+                ("on time".to_string(), "17:05".to_string(), true),
+                ("late".to_string(), "17:15".to_string(), false),
+                ("added".to_string(), "17:35".to_string(), false)
+            ]
+        );
+        // without any predictions, the schedule is counted down to
+        assert_eq!(train_times(Vec::new()).len(), scheduled.len());
+    }
+
+    #[test]
+    fn trip_updates_with_only_delays() {
+        let delayed = |stop_id: &str| StopTimeUpdate {
+            arrival: None,
+            departure: Some(StopTimeEvent {
+                delay: Some(120),
+                time: None,
+            }),
+            ..stop(stop_id, 0, None)
+        };
+        let entity = vec![
+            trip_update("scheduled", "CR-Needham", 1, vec![delayed("NHRML-0127-01")]),
+            // not in the static schedule, so there is nothing to add the delay to
+            trip_update(
+                "unscheduled",
+                "CR-Needham",
+                1,
+                vec![delayed("NHRML-0127-01")],
+            ),
+        ];
+        let feed_bytes = FeedMessage { entity }.encode_to_vec();
+        let now = Utc.timestamp_opt(NOW, 0).unwrap().with_timezone(&New_York);
+        let scheduled_time = |trip_id: &str, stop_id: &str, time_type| {
+            (trip_id == "scheduled"
+                && stop_id == "NHRML-0127-01"
+                && time_type == TimeType::Departure)
+                .then(|| now + chrono::Duration::minutes(10))
+        };
+        let trains = feed_train_times(
+            &feed_bytes,
+            "1",
+            &strings(&["NHRML-0127-01"]),
+            &[],
+            &strings(&["CR-Needham"]),
+            TimeType::Best,
+            Vec::new(),
+            &scheduled_time,
+            now,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(trains.len(), 1);
+        assert_eq!(trains[0].trip_id, "scheduled");
+        assert_eq!(trains[0].time.format("%H:%M").to_string(), "17:12");
+        assert_eq!(trains[0].delay(), Some(chrono::Duration::minutes(2)));
+    }
+
+    fn vehicle(trip_id: &str, current_stop_sequence: u32, current_status: i32) -> FeedEntity {
+        FeedEntity {
+            is_deleted: None,
+            trip_update: None,
+            vehicle: Some(GtfsVehiclePosition {
+                trip: Some(TripDescriptor {
+                    trip_id: Some(trip_id.to_string()),
+                    schedule_relationship: None,
+                    route_id: Some("CR-Needham".to_string()),
+                    direction_id: Some(1),
+                }),
+                current_stop_sequence: Some(current_stop_sequence),
+                current_status: Some(current_status),
+                stop_id: Some("NEC-2203-02".to_string()),
+            }),
+            alert: None,
+        }
+    }
+
+    #[test]
+    fn vehicle_positions() {
+        let now = Utc.timestamp_opt(NOW, 0).unwrap().with_timezone(&New_York);
+        let train = TrainTime {
+            time: now,
+            scheduled: None,
+            predicted: Some(now),
+            route: "CR-Needham".to_string(),
+            trip_id: "trip".to_string(),
+            destination_arrival: None,
+            track: None,
+            stop_sequence: Some(50),
+            occupancy: None,
+            time_type: TimeType::Departure,
+            offline: false,
+        };
+        // the remaining stops, which are not numbered consecutively
+        let stops = [20, 30, 40, 50, 60]
+            .iter()
+            .map(|sequence| StopTimeUpdate {
+                stop_sequence: Some(*sequence),
+                ..stop("NEC-2203-02", 0, None)
+            })
+            .collect();
+        let trip_update_bytes = FeedMessage {
+            entity: vec![trip_update("trip", "CR-Needham", 1, stops)],
+        }
+        .encode_to_vec();
+        let position = |vehicles: Vec<FeedEntity>| {
+            let vehicle_bytes = FeedMessage { entity: vehicles }.encode_to_vec();
+            feed_vehicle_position(&vehicle_bytes, &trip_update_bytes, &train, &|stop_id| {
+                format!("{} name", stop_id)
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            position(vec![vehicle("other trip", 40, 2), vehicle("trip", 20, 2)]),
+            Some(VehiclePosition::StopsAway(3))
+        );
+        assert_eq!(
+            position(vec![vehicle("trip", 40, 1)]),
+            Some(VehiclePosition::StoppedAt("NEC-2203-02 name".to_string()))
+        );
+        // not started yet, or already past the station
+        assert_eq!(position(vec![vehicle("other trip", 40, 2)]), None);
+        assert_eq!(position(vec![vehicle("trip", 60, 2)]), None);
+    }
+
+    fn alert(
+        header: &str,
+        route_id: Option<&str>,
+        stop_id: Option<&str>,
+        active_period: Vec<TimeRange>,
+    ) -> FeedEntity {
+        FeedEntity {
+            is_deleted: None,
+            trip_update: None,
+            vehicle: None,
+            alert: Some(GtfsAlert {
+                active_period,
+                informed_entity: vec![EntitySelector {
+                    route_id: route_id.map(|route_id| route_id.to_string()),
+                    stop_id: stop_id.map(|stop_id| stop_id.to_string()),
+                    direction_id: None,
+                }],
+                effect: Some(3),
+                header_text: Some(TranslatedString {
+                    translation: vec![
+                        Translation {
+                            text: Some(format!("{} (es)", header)),
+                            language: Some("es".to_string()),
+                        },
+                        Translation {
+                            text: Some(header.to_string()),
+                            language: Some("en".to_string()),
+                        },
+                    ],
+                }),
+            }),
+        }
+    }
+
+    #[test]
+    fn alerts_for_routes_and_stops() {
+        let now = NOW as u64;
+        let ended = vec![TimeRange {
+            start: Some(now - 7200),
+            end: Some(now - 3600),
+        }];
+        let active = vec![TimeRange {
+            start: Some(now - 3600),
+            end: None,
+        }];
+        let entity = vec![
+            alert("Needham delays", Some("CR-Needham"), None, active),
+            alert("Elevator out", None, Some("place-NHRML-0127"), vec![]),
+            alert("Earlier delays", Some("CR-Needham"), None, ended),
+            alert("Franklin delays", Some("CR-Franklin"), None, vec![]),
+        ];
+        let feed_bytes = FeedMessage { entity }.encode_to_vec();
+        let alerts = feed_alerts(
+            &feed_bytes,
+            "1",
+            &strings(&["place-NHRML-0127"]),
+            &strings(&["CR-Needham"]),
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|alert| alert.header.as_str())
+                .collect::<Vec<&str>>(),
+            ["Needham delays", "Elevator out"]
+        );
+        assert_eq!(alerts[0].effect, "SIGNIFICANT_DELAYS");
+    }
+}
@@ -0,0 +1,909 @@
+use crate::mbta_info::{
+    input_name, name_aliases, vehicle_type, Catalog, RouteDetails, StationDetails, StationInfo,
+    VehicleInfo, CATALOG_SCHEMA_VERSION,
+};
+use crate::service_date::{service_date, service_day_reference};
+use crate::train_time::{filter_destination, TimeType, TrainTime};
+use crate::transit_provider::Stop;
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+/// Default location of the offline schedule index built from the GTFS zip, next to the catalog
+/// in the user's cache directory, ie ~/.cache/mbta_countdown/gtfs_index.bin, or the working
+/// directory when there is none
+pub fn default_gtfs_index_file() -> PathBuf {
+    match dirs::cache_dir() {
+        Some(cache_dir) => cache_dir.join("mbta_countdown").join("gtfs_index.bin"),
+        None => PathBuf::from("mbta_gtfs_index.bin"),
+    }
+}
+
+/// Compact offline schedule built from the GTFS static feed.  Only the columns needed to find
+/// the vehicle times at a stop are kept
+#[derive(Serialize, Deserialize)]
+pub struct GtfsIndex {
+    /// route ids, referenced by position within trips
+    routes: Vec<String>,
+    /// route names and GTFS route types, in the same order as routes
+    route_names: Vec<String>,
+    route_types: Vec<u8>,
+    /// parent station id, or stop id for stops without a parent -> stop name
+    station_names: HashMap<String, String>,
+    /// services from calendar.txt and calendar_dates.txt, referenced by position within trips
+    services: Vec<Service>,
+    trips: Vec<Trip>,
+    /// child stop id -> parent station id
+    parents: HashMap<String, String>,
+    /// parent station id, or stop id for stops without a parent -> all vehicle times at the stop
+    stop_times: HashMap<String, Vec<StopTime>>,
+}
+
+/// Days a service runs.  Dates are stored as the GTFS YYYYMMDD number
+#[derive(Serialize, Deserialize, Default)]
+struct Service {
+    /// Monday through Sunday
+    weekdays: [bool; 7],
+    start_date: u32,
+    end_date: u32,
+    added_dates: Vec<u32>,
+    removed_dates: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Trip {
+    /// GTFS trip id, matching the trip ids of the realtime feeds
+    id: String,
+    route: u32,
+    service: u32,
+    direction_id: u8,
+}
+
+/// Arrival and departure seconds past the start of the service day
+#[derive(Serialize, Deserialize)]
+struct StopTime {
+    trip: u32,
+    arrival: Option<u32>,
+    departure: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CalendarRow {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: u32,
+    end_date: u32,
+}
+
+#[derive(Deserialize)]
+struct CalendarDateRow {
+    service_id: String,
+    date: u32,
+    exception_type: u8,
+}
+
+#[derive(Deserialize)]
+struct TripRow {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    direction_id: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct RouteRow {
+    route_id: String,
+    route_short_name: Option<String>,
+    route_long_name: Option<String>,
+    /// base or extended GTFS route type, ie 3 or 700 for buses
+    route_type: u16,
+}
+
+#[derive(Deserialize)]
+struct StopRow {
+    stop_id: String,
+    stop_name: String,
+    parent_station: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StopTimeRow {
+    trip_id: String,
+    arrival_time: Option<String>,
+    departure_time: Option<String>,
+    stop_id: String,
+}
+
+impl GtfsIndex {
+    /// Reads the GTFS static zip, such as the MBTA_GTFS.zip download, and builds the index
+    pub fn import<P: AsRef<Path>>(gtfs_zip: P) -> Result<GtfsIndex, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(gtfs_zip)?))?;
+
+        // services from the calendar, with any added or removed dates.  Feeds can leave out
+        // calendar.txt and define every service by its dates in calendar_dates.txt
+        let mut service_positions = HashMap::new();
+        let mut services: Vec<Service> = Vec::new();
+        read_optional_rows(&mut archive, "calendar.txt", |row: CalendarRow| {
+            service_positions.insert(row.service_id, services.len() as u32);
+            services.push(Service {
+                weekdays: [
+                    row.monday == 1,
+                    row.tuesday == 1,
+                    row.wednesday == 1,
+                    row.thursday == 1,
+                    row.friday == 1,
+                    row.saturday == 1,
+                    row.sunday == 1,
+                ],
+                start_date: row.start_date,
+                end_date: row.end_date,
+                ..Default::default()
+            });
+        })?;
+        // calendar_dates.txt is optional and can define services not within calendar.txt
+        read_optional_rows(
+            &mut archive,
+            "calendar_dates.txt",
+            |row: CalendarDateRow| {
+                let position = *service_positions.entry(row.service_id).or_insert_with(|| {
+                    services.push(Service::default());
+                    services.len() as u32 - 1
+                });
+                let service = &mut services[position as usize];
+                if row.exception_type == 1 {
+                    service.added_dates.push(row.date)
+                } else {
+                    service.removed_dates.push(row.date)
+                }
+            },
+        )?;
+
+        // routes with the name riders know them by
+        let mut route_positions = HashMap::new();
+        let mut routes = Vec::new();
+        let mut route_names = Vec::new();
+        let mut route_types = Vec::new();
+        read_rows(&mut archive, "routes.txt", |row: RouteRow| {
+            route_positions.insert(row.route_id.clone(), routes.len() as u32);
+            let route_type = base_route_type(row.route_type);
+            // bus routes are known by their number, most others by their long name
+            let name = match (row.route_long_name, row.route_short_name) {
+                (Some(long_name), _) if !long_name.is_empty() && route_type != 3 => long_name,
+                (_, Some(short_name)) if !short_name.is_empty() => short_name,
+                _ => row.route_id.clone(),
+            };
+            routes.push(row.route_id);
+            route_names.push(name);
+            route_types.push(route_type);
+        })?;
+
+        // trips with their route and direction
+        let mut trip_positions = HashMap::new();
+        let mut trips = Vec::new();
+        read_rows(&mut archive, "trips.txt", |row: TripRow| {
+            let route = match route_positions.get(&row.route_id) {
+                Some(route) => *route,
+                None => return,
+            };
+            if let Some(service) = service_positions.get(&row.service_id) {
+                trip_positions.insert(row.trip_id.clone(), trips.len() as u32);
+                trips.push(Trip {
+                    id: row.trip_id,
+                    route,
+                    service: *service,
+                    direction_id: row.direction_id.unwrap_or(0),
+                });
+            }
+        })?;
+
+        // platforms are grouped into their parent station, which is what the API uses
+        let mut parents = HashMap::new();
+        let mut station_names = HashMap::new();
+        read_rows(&mut archive, "stops.txt", |row: StopRow| {
+            match row.parent_station {
+                Some(parent) if !parent.is_empty() => {
+                    parents.insert(row.stop_id, parent);
+                }
+                _ => {
+                    station_names.insert(row.stop_id, row.stop_name);
+                }
+            }
+        })?;
+
+        let mut stop_times: HashMap<String, Vec<StopTime>> = HashMap::new();
+        read_rows(&mut archive, "stop_times.txt", |row: StopTimeRow| {
+            if let Some(trip) = trip_positions.get(&row.trip_id) {
+                let stop = parents.get(&row.stop_id).unwrap_or(&row.stop_id);
+                stop_times.entry(stop.clone()).or_default().push(StopTime {
+                    trip: *trip,
+                    arrival: row.arrival_time.as_deref().and_then(parse_gtfs_time),
+                    departure: row.departure_time.as_deref().and_then(parse_gtfs_time),
+                });
+            }
+        })?;
+
+        Ok(GtfsIndex {
+            routes,
+            route_names,
+            route_types,
+            station_names,
+            services,
+            trips,
+            parents,
+            stop_times,
+        })
+    }
+
+    /// Loads a previously saved index.  An index saved by an older version cannot be read, and
+    /// needs to be imported again
+    pub fn load<P: AsRef<Path>>(index_file: P) -> Result<GtfsIndex, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(index_file)?);
+        bincode::deserialize_from(reader).map_err(|err| {
+            format!(
+                "Unable to read the GTFS index, import it again with --import_gtfs: {}",
+                err
+            )
+            .into()
+        })
+    }
+
+    /// Saves the index so that the GTFS zip does not need to be parsed on every start, creating
+    /// its directory if needed
+    pub fn save<P: AsRef<Path>>(&self, index_file: P) -> Result<(), Box<dyn Error>> {
+        if let Some(index_dir) = index_file.as_ref().parent() {
+            fs::create_dir_all(index_dir)?;
+        }
+        let writer = BufWriter::new(File::create(index_file)?);
+        bincode::serialize_into(writer, self)?;
+        Ok(())
+    }
+
+    /// Creates the catalog in the same form as `mbta_info::all_mbta_info`, so that any agency's
+    /// GTFS can be selected from the command line.  The index does not keep the station
+    /// locations or accessibility, so only the names and platforms are in the details
+    pub fn catalog(&self) -> Catalog {
+        let mut vehicle_info: VehicleInfo = HashMap::new();
+        let mut routes = HashMap::new();
+        for ((route, name), route_type) in self
+            .routes
+            .iter()
+            .zip(self.route_names.iter())
+            .zip(self.route_types.iter())
+        {
+            vehicle_info
+                .entry(vehicle_type(*route_type).to_string())
+                .or_default()
+                .insert(input_name(name), route.clone());
+            routes.insert(
+                route.clone(),
+                RouteDetails {
+                    name: name.clone(),
+                    route_type: *route_type,
+                    aliases: name_aliases(name),
+                    ..RouteDetails::default()
+                },
+            );
+        }
+
+        let mut station_info: StationInfo = HashMap::new();
+        for (station, stop_times) in &self.stop_times {
+            let mut station_routes = stop_times
+                .iter()
+                .map(|stop_time| {
+                    self.routes[self.trips[stop_time.trip as usize].route as usize].clone()
+                })
+                .collect::<Vec<String>>();
+            station_routes.sort_unstable();
+            station_routes.dedup();
+            let name = self.station_names.get(station).unwrap_or(station);
+            station_info
+                .entry(input_name(name))
+                .or_default()
+                .insert(station.clone(), station_routes);
+        }
+
+        let stations = station_info
+            .values()
+            .flat_map(|codes| codes.keys())
+            .map(|station| {
+                let name = self.station_names.get(station).unwrap_or(station);
+                let details = StationDetails {
+                    name: name.clone(),
+                    aliases: name_aliases(name),
+                    child_stops: self.platforms(std::slice::from_ref(station))[1..].to_vec(),
+                    ..StationDetails::default()
+                };
+                (station.clone(), details)
+            })
+            .collect();
+        Catalog {
+            schema_version: CATALOG_SCHEMA_VERSION,
+            created: None,
+            source: "GTFS schedule".to_string(),
+            vehicle_info,
+            station_info,
+            routes,
+            stations,
+        }
+    }
+
+    /// The stations along with all of their platforms
+    pub fn platforms(&self, stations: &[String]) -> Vec<String> {
+        let mut platforms = stations.to_vec();
+        platforms.extend(
+            self.parents
+                .iter()
+                .filter(|(_, parent)| stations.contains(parent))
+                .map(|(stop, _)| stop.clone()),
+        );
+        platforms
+    }
+
+    /// Scheduled times after now for the current service date, sorted with the soonest first.
+    /// Mirrors `train_time::train_times` for when the API cannot be reached
+    pub fn train_times(
+        &self,
+        dir_code: &str,
+        stations: &[String],
+        route_codes: &[String],
+        destinations: &[String],
+        time_type: TimeType,
+        timezone: Tz,
+    ) -> Option<Vec<TrainTime>> {
+        let now = Utc::now().with_timezone(&timezone);
+        let mut all_times = self
+            .service_day_times(dir_code, stations, route_codes, time_type, &now)
+            .into_iter()
+            .filter(|train| train.time > now)
+            .collect::<Vec<TrainTime>>();
+        all_times.sort_by_key(|train| train.time);
+        if !destinations.is_empty() {
+            let arrivals = self
+                .service_day_times(dir_code, destinations, route_codes, TimeType::Arrival, &now)
+                .into_iter()
+                .map(|train| (train.trip_id, train.time))
+                .collect();
+            all_times = filter_destination(all_times, &arrivals);
+        }
+        if all_times.is_empty() {
+            return None;
+        }
+        Some(all_times)
+    }
+
+    /// The last and first scheduled times of the current service date.  Mirrors
+    /// `train_time::max_min_times` for when the API cannot be reached
+    pub fn max_min_times(
+        &self,
+        dir_code: &str,
+        stations: &[String],
+        route_codes: &[String],
+        time_type: TimeType,
+        timezone: Tz,
+    ) -> Option<[DateTime<Tz>; 2]> {
+        let now = Utc::now().with_timezone(&timezone);
+        let all_times = self.service_day_times(dir_code, stations, route_codes, time_type, &now);
+        let first = all_times.iter().map(|train| train.time).min()?;
+        let last = all_times.iter().map(|train| train.time).max()?;
+        Some([last, first])
+    }
+
+    /// The scheduled arrival or departure of the trip at a station or platform within the service
+    /// date of `now`, for the realtime feeds that only give the delay
+    pub fn scheduled_time(
+        &self,
+        trip_id: &str,
+        stop_id: &str,
+        time_type: TimeType,
+        now: &DateTime<Tz>,
+    ) -> Option<DateTime<Tz>> {
+        let stop = self.parents.get(stop_id).map_or(stop_id, String::as_str);
+        let (seconds, _) = self
+            .stop_times
+            .get(stop)?
+            .iter()
+            .find(|stop_time| self.trips[stop_time.trip as usize].id == trip_id)?
+            .seconds(time_type)?;
+        let reference = service_day_reference(service_date(now), &now.timezone());
+        Some(reference + Duration::seconds(seconds as i64))
+    }
+
+    /// The stop with the stop id, along with the routes stopping at its station, or None when
+    /// the schedule has no such stop
+    pub fn stop(&self, stop_id: &str) -> Option<Stop> {
+        let parent_station = self.parents.get(stop_id);
+        if parent_station.is_none() && !self.station_names.contains_key(stop_id) {
+            return None;
+        }
+        // stop times are kept by station, so a platform has the routes of its whole station
+        let station = parent_station.map_or(stop_id, String::as_str);
+        let mut routes = self
+            .stop_times
+            .get(station)
+            .into_iter()
+            .flatten()
+            .map(|stop_time| {
+                self.routes[self.trips[stop_time.trip as usize].route as usize].clone()
+            })
+            .collect::<Vec<String>>();
+        routes.sort_unstable();
+        routes.dedup();
+        Some(Stop {
+            name: self.stop_name(stop_id),
+            parent_station: parent_station.cloned(),
+            routes,
+        })
+    }
+
+    /// Name of a station, or of the station a platform is part of
+    pub fn stop_name(&self, stop_id: &str) -> String {
+        let stop = self.parents.get(stop_id).map_or(stop_id, String::as_str);
+        self.station_names
+            .get(stop)
+            .cloned()
+            .unwrap_or_else(|| stop.to_string())
+    }
+
+    /// All times at the stations for the routes and direction within the service date of `now`.
+    /// A trip stopping at more than one of the stations is only counted at its earliest stop
+    fn service_day_times(
+        &self,
+        dir_code: &str,
+        stations: &[String],
+        route_codes: &[String],
+        time_type: TimeType,
+        now: &DateTime<Tz>,
+    ) -> Vec<TrainTime> {
+        let date = service_date(now);
+        let reference = service_day_reference(date, &now.timezone());
+        let date_number = date.year() as u32 * 10000 + date.month() * 100 + date.day();
+        let weekday = date.weekday().num_days_from_monday() as usize;
+        let direction_id = dir_code.parse::<u8>().unwrap_or(0);
+
+        // trip index -> train time
+        let mut trip_times: HashMap<u32, TrainTime> = HashMap::new();
+        for station in stations {
+            let stop = self
+                .parents
+                .get(station)
+                .map(|parent| parent.as_str())
+                .unwrap_or(station);
+            let stop_times = match self.stop_times.get(stop) {
+                Some(stop_times) => stop_times,
+                None => continue,
+            };
+            for stop_time in stop_times {
+                let trip = &self.trips[stop_time.trip as usize];
+                let service = &self.services[trip.service as usize];
+                let route = &self.routes[trip.route as usize];
+                if trip.direction_id != direction_id
+                    || !route_codes.contains(route)
+                    || !service.runs_on(date_number, weekday)
+                {
+                    continue;
+                }
+                let (seconds, found_type) = match stop_time.seconds(time_type) {
+                    Some(seconds) => seconds,
+                    None => continue,
+                };
+                let time = reference + Duration::seconds(seconds as i64);
+                if trip_times
+                    .get(&stop_time.trip)
+                    .is_some_and(|earlier| earlier.time <= time)
+                {
+                    continue;
+                }
+                trip_times.insert(
+                    stop_time.trip,
+                    TrainTime {
+                        time,
+                        scheduled: Some(time),
+                        predicted: None,
+                        route: route.clone(),
+                        trip_id: trip.id.clone(),
+                        destination_arrival: None,
+                        track: None,
+                        stop_sequence: None,
+                        occupancy: None,
+                        time_type: found_type,
+                        offline: true,
+                    },
+                );
+            }
+        }
+        trip_times.into_values().collect()
+    }
+}
+
+impl StopTime {
+    /// The arrival or departure seconds, along with which of the two was found
+    fn seconds(&self, time_type: TimeType) -> Option<(u32, TimeType)> {
+        match time_type {
+            TimeType::Arrival => Some((self.arrival?, TimeType::Arrival)),
+            TimeType::Departure => Some((self.departure?, TimeType::Departure)),
+            TimeType::Best => self
+                .departure
+                .map(|seconds| (seconds, TimeType::Departure))
+                .or_else(|| self.arrival.map(|seconds| (seconds, TimeType::Arrival))),
+        }
+    }
+}
+
+impl Service {
+    /// Whether the service runs on the date, given as a GTFS YYYYMMDD number
+    fn runs_on(&self, date: u32, weekday: usize) -> bool {
+        if self.removed_dates.contains(&date) {
+            return false;
+        }
+        self.added_dates.contains(&date)
+            || (self.start_date <= date && date <= self.end_date && self.weekdays[weekday])
+    }
+}
+
+/// The base GTFS route type of an extended route type, ie 3 for the 700 bus services, so feeds
+/// using the extended types are grouped the same way.  Types without a base type, such as air and
+/// taxi services, are grouped with the buses
+fn base_route_type(route_type: u16) -> u8 {
+    match route_type {
+        0..=12 => route_type as u8,
+        100..=199 => 2,
+        400..=404 => 1,
+        405 => 12,
+        800..=899 => 11,
+        900..=999 => 0,
+        1000..=1099 | 1200..=1299 => 4,
+        1300..=1399 => 6,
+        1400..=1499 => 7,
+        _ => 3,
+    }
+}
+
+/// Reads each row of a CSV file within the GTFS zip.  Rows are streamed rather than collected
+/// because stop_times.txt is too large to hold in memory on a Raspberry Pi zero
+fn read_rows<T, F>(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    file_name: &str,
+    mut each_row: F,
+) -> Result<(), Box<dyn Error>>
+where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(T),
+{
+    let mut reader = csv::Reader::from_reader(archive.by_name(file_name)?);
+    for row in reader.deserialize() {
+        each_row(row?);
+    }
+    Ok(())
+}
+
+/// Reads each row of a CSV file within the GTFS zip that feeds may leave out, treating a missing
+/// file as empty
+fn read_optional_rows<T, F>(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    file_name: &str,
+    each_row: F,
+) -> Result<(), Box<dyn Error>>
+where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(T),
+{
+    if archive.by_name(file_name).is_err() {
+        return Ok(());
+    }
+    read_rows(archive, file_name, each_row)
+}
+
+/// Converts a GTFS `HH:MM:SS` time, which can be past 24:00:00, into seconds
+fn parse_gtfs_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':').map(|part| part.parse::<u32>());
+    let hours = parts.next()?.ok()?;
+    let minutes = parts.next()?.ok()?;
+    let seconds = parts.next()?.ok()?;
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+    use std::io::Write;
+
+    /// Index with one route and one trip, stopping at Needham Heights at 8:00 and, past
+    /// midnight, at 25:30
+    fn index(service: Service) -> GtfsIndex {
+        let mut stop_times = HashMap::new();
+        stop_times.insert(
+            "place-NHRML-0127".to_string(),
+            vec![
+                StopTime {
+                    trip: 0,
+                    arrival: Some(8 * 3600),
+                    departure: Some(8 * 3600),
+                },
+                StopTime {
+                    trip: 1,
+                    arrival: parse_gtfs_time("25:30:00"),
+                    departure: parse_gtfs_time("25:30:00"),
+                },
+            ],
+        );
+        GtfsIndex {
+            routes: vec!["CR-Needham".to_string()],
+            route_names: vec!["Needham Line".to_string()],
+            route_types: vec![2],
+            station_names: HashMap::new(),
+            services: vec![service],
+            trips: vec![
+                Trip {
+                    id: "CR-Weekday-1".to_string(),
+                    route: 0,
+                    service: 0,
+                    direction_id: 1,
+                },
+                Trip {
+                    id: "CR-Weekday-2".to_string(),
+                    route: 0,
+                    service: 0,
+                    direction_id: 1,
+                },
+            ],
+            parents: HashMap::new(),
+            stop_times,
+        }
+    }
+
+    /// Times at Needham Heights as "MM-DD HH:MM", soonest first
+    fn times(index: &GtfsIndex, now: &str) -> Vec<String> {
+        let now = New_York
+            .from_local_datetime(&NaiveDateTime::parse_from_str(now, "%Y-%m-%d %H:%M").unwrap())
+            .unwrap();
+        let mut times = index
+            .service_day_times(
+                "1",
+                &["place-NHRML-0127".to_string()],
+                &["CR-Needham".to_string()],
+                TimeType::Departure,
+                &now,
+            )
+            .into_iter()
+            .map(|train| train.time)
+            .collect::<Vec<DateTime<Tz>>>();
+        times.sort();
+        times
+            .iter()
+            .map(|time| time.format("%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn gtfs_times() {
+        assert_eq!(parse_gtfs_time("08:05:30"), Some(8 * 3600 + 5 * 60 + 30));
+        assert_eq!(parse_gtfs_time(" 8:05:30"), Some(8 * 3600 + 5 * 60 + 30));
+        // times past midnight stay on the previous service day
+        assert_eq!(parse_gtfs_time("24:00:00"), Some(24 * 3600));
+        assert_eq!(parse_gtfs_time("25:30:00"), Some(25 * 3600 + 30 * 60));
+        assert_eq!(parse_gtfs_time("08:05"), None);
+        assert_eq!(parse_gtfs_time(""), None);
+    }
+
+    #[test]
+    fn services_with_calendar_dates() {
+        let weekdays = Service {
+            weekdays: [true, true, true, true, true, false, false],
+            start_date: 20210701,
+            end_date: 20210731,
+            // the 4th of July holiday is observed on Monday the 5th, with an extra Saturday
+            added_dates: vec![20210710],
+            removed_dates: vec![20210705],
+        };
+        // Friday
+        assert!(weekdays.runs_on(20210709, 4));
+        // removed Monday
+        assert!(!weekdays.runs_on(20210705, 0));
+        // added Saturday, while other Saturdays do not run
+        assert!(weekdays.runs_on(20210710, 5));
+        assert!(!weekdays.runs_on(20210717, 5));
+        // outside of the calendar
+        assert!(!weekdays.runs_on(20210802, 0));
+        // services only from calendar_dates.txt run on their added dates
+        let dates_only = Service {
+            added_dates: vec![20210709],
+            ..Service::default()
+        };
+        assert!(dates_only.runs_on(20210709, 4));
+        assert!(!dates_only.runs_on(20210716, 4));
+    }
+
+    #[test]
+    fn service_day_past_midnight() {
+        // only runs on Friday the 9th
+        let index = index(Service {
+            added_dates: vec![20210709],
+            ..Service::default()
+        });
+        let friday = ["07-09 08:00", "07-10 01:30"];
+        assert_eq!(times(&index, "2021-07-09 12:00"), friday);
+        // before 3 am Saturday is still Friday's service day
+        assert_eq!(times(&index, "2021-07-10 01:00"), friday);
+        assert_eq!(times(&index, "2021-07-10 02:59"), friday);
+        assert!(times(&index, "2021-07-10 03:00").is_empty());
+        // before 3 am Friday is Thursday's service day
+        assert!(times(&index, "2021-07-09 02:00").is_empty());
+    }
+
+    #[test]
+    fn scheduled_times_of_trips() {
+        let mut index = index(Service {
+            added_dates: vec![20210709],
+            ..Service::default()
+        });
+        index
+            .parents
+            .insert("NHRML-0127-01".to_string(), "place-NHRML-0127".to_string());
+        index.station_names.insert(
+            "place-NHRML-0127".to_string(),
+            "Needham Heights".to_string(),
+        );
+        let now = New_York.with_ymd_and_hms(2021, 7, 10, 1, 0, 0).unwrap();
+        let time = |trip_id, stop_id| {
+            index
+                .scheduled_time(trip_id, stop_id, TimeType::Departure, &now)
+                .map(|time| time.format("%m-%d %H:%M").to_string())
+        };
+        // platforms are found at their station, and times past midnight on the next day
+        assert_eq!(
+            time("CR-Weekday-2", "NHRML-0127-01").as_deref(),
+            Some("07-10 01:30")
+        );
+        assert_eq!(
+            time("CR-Weekday-1", "place-NHRML-0127").as_deref(),
+            Some("07-09 08:00")
+        );
+        assert_eq!(time("CR-Weekday-3", "place-NHRML-0127"), None);
+        assert_eq!(index.stop_name("NHRML-0127-01"), "Needham Heights");
+        assert_eq!(index.stop_name("place-NEC-2203"), "place-NEC-2203");
+    }
+
+    #[test]
+    fn stops_by_stop_id() {
+        let mut index = index(Service::default());
+        index
+            .parents
+            .insert("NHRML-0127-01".to_string(), "place-NHRML-0127".to_string());
+        index.station_names.insert(
+            "place-NHRML-0127".to_string(),
+            "Needham Heights".to_string(),
+        );
+        let platform = index.stop("NHRML-0127-01").unwrap();
+        assert_eq!(platform.name, "Needham Heights");
+        assert_eq!(platform.parent_station.as_deref(), Some("place-NHRML-0127"));
+        assert_eq!(platform.routes, ["CR-Needham"]);
+        let station = index.stop("place-NHRML-0127").unwrap();
+        assert_eq!(station.parent_station, None);
+        assert_eq!(station.routes, ["CR-Needham"]);
+        assert!(index.stop("1234").is_none());
+    }
+
+    #[test]
+    fn several_stations_and_routes() {
+        let mut index = index(Service {
+            added_dates: vec![20210709],
+            ..Service::default()
+        });
+        index.routes.push("CR-Franklin".to_string());
+        index.trips.push(Trip {
+            id: "CR-Weekday-3".to_string(),
+            route: 1,
+            service: 0,
+            direction_id: 1,
+        });
+        // the first trip reaches Needham Center later and the second one earlier
+        index.stop_times.insert(
+            "place-NEC-2203".to_string(),
+            vec![
+                StopTime {
+                    trip: 0,
+                    arrival: Some(8 * 3600 + 300),
+                    departure: Some(8 * 3600 + 300),
+                },
+                StopTime {
+                    trip: 1,
+                    arrival: parse_gtfs_time("25:20:00"),
+                    departure: parse_gtfs_time("25:20:00"),
+                },
+                StopTime {
+                    trip: 2,
+                    arrival: Some(9 * 3600),
+                    departure: Some(9 * 3600),
+                },
+            ],
+        );
+        let now = New_York.with_ymd_and_hms(2021, 7, 9, 12, 0, 0).unwrap();
+        let mut trains = index
+            .service_day_times(
+                "1",
+                &["place-NHRML-0127".to_string(), "place-NEC-2203".to_string()],
+                &["CR-Needham".to_string(), "CR-Franklin".to_string()],
+                TimeType::Departure,
+                &now,
+            )
+            .into_iter()
+            .map(|train| (train.time.format("%m-%d %H:%M").to_string(), train.route))
+            .collect::<Vec<(String, String)>>();
+        trains.sort();
+        // each trip is counted once, at its earliest stop
+        assert_eq!(
+            trains,
+            [
+                ("07-09 08:00".to_string(), "CR-Needham".to_string()),
+                ("07-09 09:00".to_string(), "CR-Franklin".to_string()),
+                ("07-10 01:20".to_string(), "CR-Needham".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn import_without_calendar() {
+        let gtfs_zip =
+            std::env::temp_dir().join(format!("mbta_countdown_gtfs_{}.zip", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&gtfs_zip).unwrap());
+        for (file_name, contents) in [
+            (
+                "calendar_dates.txt",
+                "service_id,date,exception_type\nfriday,20210709,1\n",
+            ),
+            (
+                "routes.txt",
+                "route_id,route_short_name,route_long_name,route_type\nCR-Needham,,Needham Line,2\n59,59,Needham Junction - Watertown Square,700\n",
+            ),
+            (
+                "trips.txt",
+                "route_id,service_id,trip_id,direction_id\nCR-Needham,friday,trip1,1\n",
+            ),
+            (
+                "stops.txt",
+                "stop_id,stop_name,parent_station\nplace-NHRML-0127,Needham Heights,\nNHRML-0127-01,Needham Heights,place-NHRML-0127\n",
+            ),
+            (
+                "stop_times.txt",
+                "trip_id,arrival_time,departure_time,stop_id\ntrip1,25:30:00,25:30:00,NHRML-0127-01\n",
+            ),
+        ] {
+            zip.start_file(file_name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        let index = GtfsIndex::import(&gtfs_zip).unwrap();
+        std::fs::remove_file(&gtfs_zip).unwrap();
+        assert_eq!(times(&index, "2021-07-10 01:00"), ["07-10 01:30"]);
+        assert!(times(&index, "2021-07-11 01:00").is_empty());
+        // extended route types are grouped with their base type
+        assert_eq!(index.route_types, [2, 3]);
+        assert_eq!(index.route_names[1], "59");
+        // trains keep the GTFS trip id for the realtime feeds
+        let now = New_York.with_ymd_and_hms(2021, 7, 10, 1, 0, 0).unwrap();
+        let trains = index.service_day_times(
+            "1",
+            &["place-NHRML-0127".to_string()],
+            &["CR-Needham".to_string()],
+            TimeType::Departure,
+            &now,
+        );
+        assert_eq!(trains[0].trip_id, "trip1");
+    }
+}
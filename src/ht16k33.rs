@@ -3,7 +3,7 @@ extern crate ht16k33;
 extern crate rppal;
 extern crate std;
 
-use chrono::prelude::*;
+use crate::train_time::{catchable, TrainTime};
 use rppal::i2c::I2c;
 use std::collections::HashMap;
 
@@ -39,6 +39,10 @@ pub struct ClockDisplay {
     minutes_single: Option<u8>,
     seconds_ten: Option<u8>,
     seconds_single: Option<u8>,
+    // the colon is turned off when the time is only from the offline schedule
+    colon: bool,
+    // the last decimal point is lit when the train is running late
+    late: bool,
 }
 
 // Functions to initialize and change clock display
@@ -64,26 +68,28 @@ impl ClockDisplay {
             minutes_single: None,
             seconds_ten: None,
             seconds_single: None,
+            colon: true,
+            late: false,
         })
     }
 
-    /// Dispalys the minutes:seconds until the next train on the clock display
+    /// Dispalys the minutes:seconds until it is time to leave for the next train that can be
+    /// caught, `walk_min` minutes before it.  The last decimal point is lit when the train is
+    /// more than `late_threshold_min` behind schedule
     pub fn display_time_until(
         &mut self,
-        train_times: &[chrono::DateTime<Local>],
-        minimum_display_min: &i64,
+        train_times: &[TrainTime],
+        walk_min: &i64,
+        late_threshold_min: &i64,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // get now time in UTC
-        let now = chrono::Local::now();
-        // get the difference between now and the train time
-        let mut diff = train_times[0].signed_duration_since(now);
-        // if difference is less than minumum display, use next train
-        if diff.num_minutes() < *minimum_display_min {
-            if train_times.len() > 1usize {
-                diff = train_times[1].signed_duration_since(now)
-            } else {
-                // if there is not a next train, clear display and end
-                if vec![
+        let now = chrono::Utc::now();
+        // count down to when to leave for the first train that can still be caught
+        let train = match catchable(train_times, *walk_min, now).into_iter().next() {
+            Some(train) => train,
+            None => {
+                // if there is not a train to catch, clear display and end
+                if [
                     self.minutes_ten,
                     self.minutes_single,
                     self.seconds_ten,
@@ -96,13 +102,17 @@ impl ClockDisplay {
                 };
                 return Ok(());
             }
-        }
+        };
+        let diff = train.leave_time(*walk_min).signed_duration_since(now);
         // separate out minutes and seconds for the display
         let minutes = diff.num_minutes();
         // Seconds as the remainder after minutes are removed
         let seconds = diff.num_seconds() % 60i64;
+        let late = train
+            .delay()
+            .is_some_and(|delay| delay.num_minutes() > *late_threshold_min);
         // Clock display only has two digits for minutes, so minutes need to be below 100
-        if *minimum_display_min < minutes && minutes < 100i64 {
+        if minutes < 100i64 {
             // find all of the new digits for displaying difference
             // first digit, which is the tens minutes
             let first = (minutes as u8) / 10u8;
@@ -113,7 +123,7 @@ impl ClockDisplay {
             // fourth digit, which is the seconds single
             let fourth = (seconds as u8) % 10u8;
             // if current display has no values, then display all of the new values
-            if vec![
+            if [
                 self.minutes_ten,
                 self.minutes_single,
                 self.seconds_ten,
@@ -126,6 +136,8 @@ impl ClockDisplay {
                 self.minutes_single = Some(second);
                 self.seconds_ten = Some(third);
                 self.seconds_single = Some(fourth);
+                self.colon = !train.offline;
+                self.late = late;
                 self.display_nums()?;
             } else {
                 // else change only the values that have changed
@@ -145,6 +157,14 @@ impl ClockDisplay {
                     self.change_number(8, &fourth)?;
                     self.seconds_single = Some(fourth);
                 }
+                if late != self.late {
+                    self.late = late;
+                    self.display_decimal_point(self.late)?;
+                }
+                if train.offline == self.colon {
+                    self.colon = !train.offline;
+                    self.display_colon(self.colon)?;
+                }
             }
         } else {
             // if minutes is greater than 100 clear dispaly and set all values to none
@@ -186,7 +206,8 @@ impl ClockDisplay {
             let leds = NUMBER_LEDS.get(&seconds_single).unwrap();
             self.switch_leds(leds, 8, true)?;
         }
-        self.display_colon(true)?;
+        self.display_colon(self.colon)?;
+        self.display_decimal_point(self.late)?;
         Ok(())
     }
 
@@ -217,6 +238,12 @@ impl ClockDisplay {
         Ok(())
     }
 
+    /// Turns on/off the decimal point after the last digit
+    fn display_decimal_point(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        // the decimal point is led 7 of each digit
+        self.switch_leds(&[7u8], 8, on)
+    }
+
     fn change_number(
         &mut self,
         location: u8,
@@ -2,8 +2,14 @@
 extern crate lazy_static;
 
 pub mod clocks;
+pub mod config;
+pub mod gtfs_realtime;
+pub mod gtfs_static;
 pub mod ht16k33;
 pub mod mbta_info;
+pub mod search;
+pub mod service_date;
 pub mod ssd1306_screen;
 pub mod tm1637;
 pub mod train_time;
+pub mod transit_provider;
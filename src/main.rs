@@ -1,13 +1,23 @@
-use chrono::prelude::*;
-use chrono::Local;
-use clap::{App, Arg};
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use clap::{App, Arg, ArgMatches, SubCommand};
 use mbta_countdown::clocks::ClockType;
+use mbta_countdown::config::{append_profile, default_config_file, Config, Settings};
+use mbta_countdown::gtfs_realtime::{
+    MBTA_ALERTS_URL, MBTA_TRIP_UPDATES_URL, MBTA_VEHICLE_POSITIONS_URL,
+};
+use mbta_countdown::gtfs_static::{default_gtfs_index_file, GtfsIndex};
+use mbta_countdown::mbta_info::{default_catalog_file, write_snapshot, Catalog};
+use mbta_countdown::search::{nearby, search, NearbyStation};
+use mbta_countdown::service_date::later_service_date;
+use mbta_countdown::train_time::{catchable, TimeType};
+use mbta_countdown::transit_provider::{Alert, GtfsProvider, MbtaProvider, Query, TransitProvider};
 use rppal::gpio;
 use std::{
-    cmp,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error,
-    io::{stdout, Read, Write},
+    io::{self, stdout, Read, Write},
+    path::{Path, PathBuf},
     process::{exit, Command},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -17,15 +27,38 @@ use std::{
 };
 use termion::{async_stdin, raw::IntoRawMode};
 
+/// Seconds the profile name is shown on the screen when switching to it
+const PROFILE_DISPLAY_SECS: u64 = 3;
+
 #[tokio::main]
 async fn main() {
-    let (dir_code, station, clock_brightness, vehicle_code, clock_type) =
-        arguments().unwrap_or_else(|err| panic!("ERROR - train_times - {}", err));
-    let minimum_display_min = 5i64;
+    let Arguments {
+        profiles,
+        hardware,
+        provider,
+    } = arguments()
+        .await
+        .unwrap_or_else(|err| panic!("ERROR - train_times - {}", err));
+    let timezone = provider.timezone();
+    // the countdown of the profile scheduled now, switched by the screen thread
+    let countdown = Arc::new(Mutex::new(
+        profiles
+            .active(&Utc::now().with_timezone(&timezone))
+            .clone(),
+    ));
+    let Countdown {
+        mut query,
+        destination_name,
+        direction_label,
+        mut walk_min,
+        ..
+    } = countdown.lock().unwrap().clone();
 
     // setup the screen as blank with 'q to quit'
     let out = stdout();
     let mut stdout_main = out.lock().into_raw_mode().unwrap();
+    // async_stdin is already buffered through a channel by termion
+    #[allow(clippy::unbuffered_bytes)]
     let mut stdin = async_stdin().bytes();
 
     write!(
@@ -45,7 +78,6 @@ async fn main() {
         termion::style::NoBold
     )
     .unwrap();
-    stdout_main.flush().unwrap();
 
     // setup variables that are passed between threads
     let quit = Arc::new(AtomicBool::new(false)); // set quit to false to have a clean quit
@@ -58,7 +90,7 @@ async fn main() {
     // setup async interrup which shuts down the device if bthe button is pressed
     let gpio = gpio::Gpio::new().unwrap_or_else(|err| panic!("ERROR - gpio - {}", err));
     let mut shutdown_pin = gpio
-        .get(13)
+        .get(hardware.shutdown_pin)
         .unwrap_or_else(|err| panic!("ERROR - pin - {}", err))
         .into_input_pulldown();
     shutdown_pin
@@ -86,91 +118,148 @@ async fn main() {
     });
     // setup countdown clock.  If the type is not TM1637, the I2C address is used, otherwise it is
     // bit banged
-    let address = match clock_type {
+    let address = match hardware.clock_type {
         ClockType::TM1637 => None,
-        ClockType::HT16K33 => Some(0x70),
+        ClockType::HT16K33 => Some(hardware.clock_address),
     };
     // Initiate the countdown clock
     let mut clock;
-    clock = mbta_countdown::clocks::Clocks::new(clock_type, clock_brightness, address)
-        .unwrap_or_else(|err| panic!("ERROR - clock - {}", err));
+    clock = mbta_countdown::clocks::Clocks::new(
+        hardware.clock_type,
+        hardware.clock_brightness,
+        address,
+        hardware.tm1637_pins,
+    )
+    .unwrap_or_else(|err| panic!("ERROR - clock - {}", err));
+    let (screen_address, min_poll_secs, max_poll_secs) = (
+        hardware.screen_address,
+        hardware.min_poll_secs,
+        hardware.max_poll_secs,
+    );
 
     // Get the scheduled and predicted train times to display and countdown from
     let train_times = Arc::new(Mutex::new(
-        mbta_countdown::train_time::train_times(&dir_code, &station, &vehicle_code)
+        provider
+            .train_times(&query)
             .await
             .unwrap_or_else(|err| panic!("ERROR - train_times - {}", err)),
     ));
+    let alerts: Arc<Mutex<Vec<Alert>>> = Arc::new(Mutex::new(Vec::new()));
 
     let train_times_clone = Arc::clone(&train_times);
+    let alerts_clone = Arc::clone(&alerts);
     let quit_clone = Arc::clone(&quit);
 
     let pause_overnight = Arc::new(AtomicBool::new(false));
     let pause_overnight_clone = Arc::clone(&pause_overnight);
+    // set while the train times have not been retrieved for 10 minutes
+    let feed_failing = Arc::new(AtomicBool::new(false));
+    let feed_failing_clone = Arc::clone(&feed_failing);
+    let countdown_clone = Arc::clone(&countdown);
 
     // spawn screen thread
     let screen_train_thread = tokio::spawn(async move {
         let mut train_time_errors = 0u8;
-        let mut screen = mbta_countdown::ssd1306_screen::ScreenDisplay::new(0x3c)
-            .unwrap_or_else(|err| panic!("ERROR - ScreenDisplay - {}", err));
+        // where the vehicle for the next train is, updated along with the train times
+        let mut vehicle_position = None;
+        let mut screen = mbta_countdown::ssd1306_screen::ScreenDisplay::new(
+            screen_address,
+            show_route(&query),
+            destination_name,
+            direction_label,
+            walk_min,
+        )
+        .unwrap_or_else(|err| panic!("ERROR - ScreenDisplay - {}", err));
 
-        // get the first and last train for the day to know when to pause the displays and not
-        // continually update when there are no trains arriving
-        let last_first =
-            mbta_countdown::train_time::max_min_times(&dir_code, &station, &vehicle_code)
-                .await
-                .unwrap_or_else(|err| panic!("Error - max min times - {}", err));
-        let mut last_time;
-        let mut first_time;
-        if let Some([last, _]) = last_first {
-            last_time = last;
-        } else {
-            panic!("Missing vehicles")
-        };
+        // the last train for the day, to know when to pause the displays and not continually
+        // update when there are no trains arriving.  Retrieved at the top of the loop
+        let mut last_time = None;
+
+        // name of the profile being counted down
+        let mut active_profile = countdown_clone.lock().unwrap().profile.clone();
 
         loop {
             // get the current time
-            let mut now = Local::now();
+            let mut now = Utc::now().with_timezone(&timezone);
+
+            // switch to the profile scheduled now, showing its name for a few seconds before its
+            // trains
+            let active = profiles.active(&now);
+            if active.profile != active_profile {
+                active_profile = active.profile.clone();
+                *countdown_clone.lock().unwrap() = active.clone();
+                query = active.query.clone();
+                walk_min = active.walk_min;
+                screen.set_countdown(
+                    show_route(&query),
+                    active.destination_name.clone(),
+                    active.direction_label.clone(),
+                    walk_min,
+                );
+                if let Some(profile) = &active_profile {
+                    screen
+                        .display_profile(profile)
+                        .unwrap_or_else(|err| panic!("ERROR - display_profile - {}", err));
+                }
+                vehicle_position = None;
+                alerts_clone.lock().unwrap().clear();
+                // an error leaves the clock blank until the next update
+                let new_train_times = provider.train_times(&query).await.unwrap_or(None);
+                *train_times_clone.lock().unwrap() = new_train_times;
+                last_time = None;
+                tokio::time::sleep(Duration::from_secs(PROFILE_DISPLAY_SECS)).await;
+            }
+
+            // get the last train of the day.  When it cannot be retrieved, or there is no service,
+            // the displays are cleared like for an empty list of trains and it is tried again
+            // after the pause
+            if last_time.is_none() {
+                last_time = provider
+                    .service_span(&query)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|[last, _]| last);
+            }
 
             // if the current time is after the last time start a pause
-            if now > last_time {
+            if let Some(last) = last_time.filter(|last| now > *last) {
                 pause_overnight_clone.store(true, Ordering::Relaxed);
 
                 screen
                     .clear_display(true)
                     .unwrap_or_else(|err| panic!("ERROR - clear_display - {}", err));
 
-                // if it is less than 3 am or the same day of the last vehicle, pause for 5 minutes
-                // and recheck the time
-                while now.hour() < 3 || now.hour() == 24 || now.day() == last_time.day() {
-                    if quit_clone.load(Ordering::Relaxed) {
+                // if it is still the same service date as the last vehicle, which runs until 3 am,
+                // pause and recheck the time every second for a quick quit or profile switch
+                while !later_service_date(&last, &now) {
+                    if quit_clone.load(Ordering::Relaxed)
+                        || profiles.is_switching(&active_profile, &now)
+                    {
                         break;
                     };
-                    tokio::time::sleep(Duration::from_secs(300)).await;
-                    now = Local::now();
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    now = Utc::now().with_timezone(&timezone);
                 }
 
-                // after 3 am get the first and last vehicle times
-                let last_first_thread =
-                    mbta_countdown::train_time::max_min_times(&dir_code, &station, &vehicle_code)
-                        .await
-                        .unwrap_or_else(|err| panic!("Error - max min times - {}", err));
-                if let Some([last, first]) = last_first_thread {
-                    last_time = last;
-                    first_time = first;
-                } else {
-                    panic!("Missing vehicles")
-                };
-
-                // get the hour one earlier than the first train to begin the countdown again
-                let one_hour = first_time.hour() - 1;
-                // Pause until one hour before the first train
-                while now.hour() < one_hour {
-                    if quit_clone.load(Ordering::Relaxed) {
-                        break;
-                    };
-                    tokio::time::sleep(Duration::from_secs(300)).await;
-                    now = Local::now();
+                // after 3 am get the first and last vehicle times.  Without them, the countdown
+                // continues and retries them after the next pause
+                last_time = None;
+                if let Some([last, first]) = provider.service_span(&query).await.ok().flatten() {
+                    last_time = Some(last);
+                    // get the time one hour earlier than the first train to begin the countdown
+                    // again
+                    let one_hour = first - chrono::Duration::hours(1);
+                    // Pause until one hour before the first train
+                    while now < one_hour {
+                        if quit_clone.load(Ordering::Relaxed)
+                            || profiles.is_switching(&active_profile, &now)
+                        {
+                            break;
+                        };
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        now = Utc::now().with_timezone(&timezone);
+                    }
                 }
 
                 // after all puases are done, return false to the other thread to allow it to
@@ -188,36 +277,31 @@ async fn main() {
             };
 
             // setting up the amount of seconds to pause between fetching and updating train data.
-            // Currently setup to pause for 1/10th the time between now and the next train.  If
-            // there are no trains, there is a 600 second pause before updating.  There is also a 15
-            // second minimum pause time setup with the max statement below
+            // Currently setup to pause for 1/10th the time between now and leaving for the next
+            // train that can be caught, kept between the min_poll_secs and max_poll_secs settings.
+            // If there are no trains to catch, the pause is max_poll_secs
             let pause_seconds;
             // if there are train times, display them on the screen, otherwise clear the display
             if let Some(ref train_times_list) = *train_times_clone.lock().unwrap() {
                 screen
-                    .display_trains(train_times_list)
+                    .display_trains(train_times_list, vehicle_position.as_ref())
                     .unwrap_or_else(|err| panic!("ERROR - display_trains - {}", err));
-                // make sure the train time is greater than now to prevent a negative train
-                // difference
-                if train_times_list[0] > now {
-                    let time_sec_diff = (train_times_list[0] - now).num_seconds();
-                    pause_seconds = cmp::max(time_sec_diff / 10, 15);
-                } else {
-                    // if the first vehicle time already passed go to the second
-                    if train_times_list.len() > 1 {
-                        let time_sec_diff = (train_times_list[0] - now).num_seconds();
-                        pause_seconds = cmp::max(time_sec_diff / 10, 15);
-                    } else {
-                        // if there are no trains later than now, setup pause time to 600 seconds
-                        pause_seconds = 600;
+                let catchable_trains =
+                    catchable(train_times_list, walk_min, now.with_timezone(&Utc));
+                pause_seconds = match catchable_trains.first() {
+                    Some(next_train) => {
+                        let time_sec_diff = (next_train.leave_time(walk_min) - now).num_seconds();
+                        (time_sec_diff / 10).clamp(min_poll_secs as i64, max_poll_secs as i64)
+                            as u64
                     }
-                }
+                    None => max_poll_secs,
+                };
             } else {
                 screen
                     .clear_display(true)
                     .unwrap_or_else(|err| panic!("ERROR - clear_display - {}", err));
-                // if there are no trains, setup the pause time to 600 seconds
-                pause_seconds = 600;
+                // if there are no trains, pause for the longest time
+                pause_seconds = max_poll_secs;
             };
 
             // async pause for 120 seconds donw in single seconds for a clean quit
@@ -229,39 +313,76 @@ async fn main() {
                         .unwrap_or_else(|err| panic!("ERROR - clear_display - {}", err));
                     break;
                 };
+                if profiles.is_switching(&active_profile, &Utc::now().with_timezone(&timezone)) {
+                    break;
+                }
+                // keep the time to leave current, dropping trains that can no longer be caught
+                if let Some(ref train_times_list) = *train_times_clone.lock().unwrap() {
+                    screen
+                        .display_trains(train_times_list, vehicle_position.as_ref())
+                        .unwrap_or_else(|err| panic!("ERROR - display_trains - {}", err));
+                }
+            }
+            // the next profile's train times are fetched once it is switched to
+            if profiles.is_switching(&active_profile, &Utc::now().with_timezone(&timezone)) {
+                continue;
             }
 
             // If there is no error on retrieving the train times from the website, update the
-            // train_times variable, otherwise allow up to 5 errors
-            if let Ok(new_train_times) =
-                mbta_countdown::train_time::train_times(&dir_code, &station, &vehicle_code).await
-            {
+            // train_times variable.  After 5 errors in a row the times are too old to count down
+            // to, so they are cleared and the failure shown until the times are retrieved again
+            let new_train_times = provider.train_times(&query).await.ok();
+            if let Some(new_train_times) = new_train_times {
+                // find where the next train that can be caught is.  This is only shown on the
+                // screen, so an error just leaves it off
+                let next_train = new_train_times
+                    .as_ref()
+                    .and_then(|list| catchable(list, walk_min, Utc::now()).into_iter().next());
+                vehicle_position = match next_train {
+                    Some(next_train) => provider.vehicle_position(&next_train).await.ok().flatten(),
+                    None => None,
+                };
                 *train_times_clone.lock().unwrap() = new_train_times;
                 train_time_errors = 0;
+                feed_failing_clone.store(false, Ordering::Relaxed);
             } else {
-                train_time_errors += 1;
-                if train_time_errors == 5 {
-                    panic!("Unable to retrieve train times for 10 minutes");
+                train_time_errors = train_time_errors.saturating_add(1);
+                if train_time_errors >= 5 {
+                    *train_times_clone.lock().unwrap() = None;
+                    vehicle_position = None;
+                    feed_failing_clone.store(true, Ordering::Relaxed);
                 }
             }
+            // alerts are only shown on the terminal, so keep the previous alerts on an error
+            if let Ok(new_alerts) = provider.alerts(&query).await {
+                *alerts_clone.lock().unwrap() = new_alerts;
+            }
         }
     });
 
+    // alert, heading and failure shown on the terminal, to only redraw when they change
+    let mut shown_alert: Option<String> = None;
+    let mut shown_heading = String::new();
+    let mut shown_failing = false;
+
     // start the loop for the countdown clock
     loop {
-        // if display thread declares a pause, pause the countdown for 5 minutes
-        let mut minutes_paused = 0u32;
+        // if display thread declares a pause, pause the countdown, checking every second so it
+        // resumes as soon as the display thread does
+        let mut seconds_paused = 0u32;
         while pause_overnight.load(Ordering::Relaxed) {
-            write!(
-                stdout_main,
-                "{}Paused for {} minutes",
-                termion::cursor::Goto(1, 3),
-                minutes_paused,
-            )
-            .unwrap();
-            stdout_main.flush().unwrap();
-            tokio::time::sleep(Duration::from_secs(300)).await;
-            minutes_paused += 5;
+            if seconds_paused.is_multiple_of(60) {
+                write!(
+                    stdout_main,
+                    "{}Paused for {} minutes",
+                    termion::cursor::Goto(1, 3),
+                    seconds_paused / 60,
+                )
+                .unwrap();
+                stdout_main.flush().unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            seconds_paused += 1;
             if quit.load(Ordering::Relaxed) {
                 break;
             };
@@ -280,10 +401,70 @@ async fn main() {
             break;
         };
         tokio::time::sleep(Duration::from_millis(250)).await;
+        // show the first service alert, if any, under the pause line
+        let alert = alerts
+            .lock()
+            .unwrap()
+            .first()
+            .map(|alert| format!("{}: {}", alert.effect, alert.header));
+        // the train times keep being retried while they cannot be retrieved
+        let failing = feed_failing.load(Ordering::Relaxed);
+        if failing != shown_failing {
+            write!(
+                stdout_main,
+                "{}{}{}",
+                termion::cursor::Goto(1, 4),
+                termion::clear::CurrentLine,
+                if failing {
+                    "Unable to retrieve train times, retrying"
+                } else {
+                    ""
+                },
+            )
+            .unwrap();
+            stdout_main.flush().unwrap();
+            shown_failing = failing;
+        }
+        if alert != shown_alert {
+            write!(
+                stdout_main,
+                "{}{}{}",
+                termion::cursor::Goto(1, 5),
+                termion::clear::CurrentLine,
+                alert.as_deref().unwrap_or(""),
+            )
+            .unwrap();
+            stdout_main.flush().unwrap();
+            shown_alert = alert;
+        }
+        // the routes and stations being counted down, after the profile name when there is one
+        let Countdown {
+            profile,
+            heading,
+            walk_min,
+            late_threshold_min,
+            ..
+        } = countdown.lock().unwrap().clone();
+        let heading = match profile {
+            Some(profile) => format!("{}: {}", profile, heading),
+            None => heading,
+        };
+        if heading != shown_heading {
+            write!(
+                stdout_main,
+                "{}{}{}",
+                termion::cursor::Goto(1, 2),
+                termion::clear::CurrentLine,
+                heading,
+            )
+            .unwrap();
+            stdout_main.flush().unwrap();
+            shown_heading = heading;
+        }
         // if there are some train times, display on clock and screen
         if let Some(ref train_times_list) = *train_times.lock().unwrap() {
             clock
-                .display_time_until(train_times_list, &minimum_display_min)
+                .display_time_until(train_times_list, &walk_min, &late_threshold_min)
                 .unwrap_or_else(|err| panic!("ERROR - display_time_until - {}", err));
         } else {
             clock
@@ -322,33 +503,127 @@ async fn main() {
     }
 }
 
-/// Gets the command line arguments
-pub fn arguments() -> Result<(String, String, u8, String, ClockType), Box<dyn error::Error>> {
-    // get station and vehicle conversions for the MBTA API
-    let (vehicle_info, station_info) = mbta_countdown::mbta_info::all_mbta_info(false)?;
-    // get a list of stations to limit the station argument input
-    let mut input_stations: Vec<&str> = station_info.keys().map(|key| key.as_str()).collect();
-    input_stations.sort_unstable();
-    // create an empty hashmap to handle errors when the key does not exist and update is called
-    let mut empty_vehicle_hashmap = HashMap::new();
-    empty_vehicle_hashmap.insert("".to_string(), "".to_string());
-    // get a list of commuter rail lines to limit the commuter rail argument input
-    let commuter_rails = vehicle_info
-        .get("Commuter_Rail")
-        .unwrap_or(&empty_vehicle_hashmap);
-    let mut input_commuter: Vec<&str> = commuter_rails.keys().map(|key| key.as_str()).collect();
-    input_commuter.sort_unstable();
-    // get a list of subway lines to limit the subway argument input
-    let subway_lines = vehicle_info.get("Subway").unwrap_or(&empty_vehicle_hashmap);
-    let mut input_subway: Vec<&str> = subway_lines.keys().map(|key| key.as_str()).collect();
-    input_subway.sort_unstable();
-    // get a list of ferry lines to limit the ferry argument input
-    let ferry_lines = vehicle_info.get("Ferry").unwrap_or(&empty_vehicle_hashmap);
-    let mut input_ferry: Vec<&str> = ferry_lines.keys().map(|key| key.as_str()).collect();
-    input_ferry.sort_unstable();
-
-    // parse arguments
-    let args = App::new("MBTA train departure display")
+/// Labels the times with their route when several are merged
+fn show_route(query: &Query) -> bool {
+    query.route_codes.len() > 1
+}
+
+/// Values parsed from the command line arguments and config file
+pub struct Arguments {
+    profiles: Profiles,
+    hardware: Hardware,
+    provider: Arc<dyn TransitProvider>,
+}
+
+/// The countdown of each profile, and the schedule switching between them
+pub struct Profiles {
+    /// Countdown used outside of the scheduled times, or the only one
+    default: Countdown,
+    /// Scheduled profile name -> its countdown
+    scheduled: BTreeMap<String, Countdown>,
+    /// Config with the schedule, left out when a profile is chosen with --profile
+    config: Option<Config>,
+}
+
+impl Profiles {
+    /// The countdown of the profile scheduled at the agency time
+    fn active(&self, now: &DateTime<Tz>) -> &Countdown {
+        self.config
+            .as_ref()
+            .and_then(|config| config.scheduled_profile(now.weekday(), now.time()))
+            .and_then(|profile| self.scheduled.get(profile))
+            .unwrap_or(&self.default)
+    }
+
+    /// Whether another profile than the one counted down is scheduled at the agency time
+    fn is_switching(&self, profile: &Option<String>, now: &DateTime<Tz>) -> bool {
+        self.active(now).profile != *profile
+    }
+}
+
+/// What is counted down and how it is shown, from the profile
+#[derive(Clone)]
+pub struct Countdown {
+    /// Name of the profile, shown when switching to it
+    profile: Option<String>,
+    query: Query,
+    /// Routes and stations counted down, by the names riders know them by
+    heading: String,
+    /// Name of the destination given with --to
+    destination_name: Option<String>,
+    /// How riders know the direction, ie "Southbound to Ashmont/Braintree"
+    direction_label: String,
+    /// Minutes to walk to the station, counted down to leave in time for the trains
+    walk_min: i64,
+    late_threshold_min: i64,
+}
+
+/// Where the displays and button are connected, and how often the train times are fetched
+pub struct Hardware {
+    clock_type: ClockType,
+    clock_brightness: u8,
+    clock_address: u8,
+    screen_address: u16,
+    /// GPIO pins of the TM1637 clock and data lines
+    tm1637_pins: (u8, u8),
+    shutdown_pin: u8,
+    min_poll_secs: u64,
+    max_poll_secs: u64,
+}
+
+/// Creates the command line parser.  Stations and lines are checked against the catalog after
+/// parsing, as they can be given by any of their names
+fn build_app<'a>() -> App<'a, 'a> {
+    // stations and lines can be given several times, or separated by commas, to merge them into
+    // one countdown
+    let station = Arg::with_name("station")
+        .short("s")
+        .long("station")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Train station, or several separated by commas");
+    let stop_number = Arg::with_name("stop_number")
+        .long("stop_number")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Stop number shown on bus stop signs, or several separated by commas");
+    let commuter_rail = Arg::with_name("commuter_rail")
+        .short("c")
+        .long("commuter_rail")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Commuter rail line, or several separated by commas");
+    let subway_line = Arg::with_name("subway_line")
+        .short("l")
+        .long("subway_line")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Subway line, or several separated by commas");
+    let ferry_line = Arg::with_name("ferry_line")
+        .short("f")
+        .long("ferry_line")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Ferry line, or several separated by commas");
+    let bus = Arg::with_name("bus")
+        .long("bus")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .use_delimiter(true)
+        .help("Bus route number, or several separated by commas");
+
+    App::new("MBTA train departure display")
         .version("0.3.2")
         .author("Rory Coffey <coffeyrt@gmail.com>")
         .about("Displays the departure of the Needham MBTA commuter rail")
@@ -357,62 +632,99 @@ pub fn arguments() -> Result<(String, String, u8, String, ClockType), Box<dyn er
                 .short("d")
                 .long("direction")
                 .takes_value(true)
-                .required_unless("update_mbta")
-                .possible_values(&["inbound", "outbound"])
-                .help("Train direction"),
+                .help("Train direction, as inbound or outbound, or the route's direction name or destination, ie Southbound or Ashmont on the Red Line"),
         )
+        .arg(station)
+        .arg(stop_number)
         .arg(
-            Arg::with_name("station")
-                .short("s")
-                .long("station")
+            Arg::with_name("to")
+                .long("to")
                 .takes_value(true)
-                .required_unless("update_mbta")
-                .possible_values(&input_stations)
-                .help("Train station"),
+                .help("Destination station.  Only trains stopping there are shown"),
         )
+        .arg(commuter_rail)
+        .arg(subway_line)
+        .arg(ferry_line)
+        .arg(bus)
         .arg(
-            Arg::with_name("commuter_rail")
-                .short("c")
-                .long("commuter_rail")
+            Arg::with_name("clock_brightness")
+                .short("b")
+                .long("clock_brightness")
                 .takes_value(true)
-                .required_unless_one(&["subway_line", "ferry_line", "update_mbta"])
-                .possible_values(&input_commuter)
-                .help("Commuter rail line"),
+                .help("Scale to set clock brightness, 0-9 or 0-7 on a TM1637 [default: 7]"),
         )
         .arg(
-            Arg::with_name("subway_line")
-                .short("l")
-                .long("subway_line")
-                .takes_value(true)
-                .required_unless_one(&["commuter_rail", "ferry_line", "update_mbta"])
-                .possible_values(&input_subway)
-                .help("Subway line"),
+            Arg::with_name("skip_full")
+                .long("skip_full")
+                .takes_value(false)
+                .help("Leave out trains reported as full"),
         )
         .arg(
-            Arg::with_name("ferry_line")
-                .short("f")
-                .long("ferry_line")
+            Arg::with_name("late_threshold")
+                .long("late_threshold")
                 .takes_value(true)
-                .required_unless_one(&["commuter_rail", "subway_line", "update_mbta"])
-                .possible_values(&input_ferry)
-                .help("Ferry line"),
+                .help("Minutes behind schedule before the clock lights its decimal point [default: 5]"),
         )
         .arg(
-            Arg::with_name("clock_brightness")
-                .short("b")
-                .long("clock_brightness")
+            Arg::with_name("walk_min")
+                .short("w")
+                .long("walk_min")
                 .takes_value(true)
-                .default_value("7")
-                .help("Scale to set clock brightness, 0-9"),
+                .help("Minutes it takes to walk to the station.  The clock counts down to when to leave, skipping trains that can no longer be caught [default: 0]"),
         )
         .arg(
             Arg::with_name("clock_type")
                 .short("t")
                 .long("clock_type")
                 .takes_value(true)
-                .default_value("HT16K33")
                 .possible_values(&["HT16K33", "TM1637"])
-                .help("Set countdown clock type"),
+                .help("Set countdown clock type [default: HT16K33]"),
+        )
+        .arg(
+            Arg::with_name("time_type")
+                .short("a")
+                .long("time_type")
+                .takes_value(true)
+                .possible_values(&["arrival", "departure", "best"])
+                .help("Count down to the arrival or departure time.  'best' uses the departure time, or the arrival time when there is no departure such as at the last stop [default: best]"),
+        )
+        .arg(
+            Arg::with_name("timezone")
+                .short("z")
+                .long("timezone")
+                .takes_value(true)
+                .default_value("America/New_York")
+                .help("Timezone of the transit agency used for all displayed times"),
+        )
+        .arg(
+            Arg::with_name("provider")
+                .short("p")
+                .long("provider")
+                .takes_value(true)
+                .default_value("mbta")
+                .possible_values(&["mbta", "gtfs"])
+                .help("MBTA V3 API, or the imported GTFS schedule with GTFS-Realtime feeds"),
+        )
+        .arg(
+            Arg::with_name("trip_updates_url")
+                .long("trip_updates_url")
+                .takes_value(true)
+                .default_value(MBTA_TRIP_UPDATES_URL)
+                .help("GTFS-Realtime trip updates URL used with '--provider gtfs'"),
+        )
+        .arg(
+            Arg::with_name("alerts_url")
+                .long("alerts_url")
+                .takes_value(true)
+                .default_value(MBTA_ALERTS_URL)
+                .help("GTFS-Realtime alerts URL used with '--provider gtfs'"),
+        )
+        .arg(
+            Arg::with_name("vehicle_positions_url")
+                .long("vehicle_positions_url")
+                .takes_value(true)
+                .default_value(MBTA_VEHICLE_POSITIONS_URL)
+                .help("GTFS-Realtime vehicle positions URL used with '--provider gtfs'"),
         )
         .arg(
             Arg::with_name("update_mbta")
@@ -421,77 +733,667 @@ pub fn arguments() -> Result<(String, String, u8, String, ClockType), Box<dyn er
                 .takes_value(false)
                 .help("Update MBTA info from their website"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("write_snapshot")
+                .long("write_snapshot")
+                .takes_value(true)
+                .help("Retrieve the MBTA info and save it as the snapshot built into the program, ie data/catalog_snapshot.json"),
+        )
+        .arg(
+            Arg::with_name("catalog")
+                .long("catalog")
+                .takes_value(true)
+                .help("File the MBTA route and station information is saved to, instead of the cache directory"),
+        )
+        .arg(
+            Arg::with_name("gtfs_index")
+                .long("gtfs_index")
+                .takes_value(true)
+                .help("File the imported GTFS schedule is saved to, instead of the cache directory"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("TOML config file with default settings and named profiles, instead of the one in the config directory"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .short("P")
+                .long("profile")
+                .takes_value(true)
+                .help("Profile in the config file to count down.  Arguments given override its settings"),
+        )
+        .arg(
+            Arg::with_name("import_gtfs")
+                .short("g")
+                .long("import_gtfs")
+                .takes_value(true)
+                .help("Import the GTFS static zip, ie MBTA_GTFS.zip, as the offline schedule used when the MBTA API cannot be reached"),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Finds stations and routes by name, along with the flags to choose them")
+                .arg(
+                    Arg::with_name("name")
+                        .multiple(true)
+                        .help("Station or route name, even partly typed or misspelled.  Without one, names are asked for until an empty line"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("nearby")
+                .about("Lists the stations closest to a location, along with the routes and directions serving them.  Needs the MBTA info updated with -u first")
+                .arg(
+                    Arg::with_name("lat")
+                        .long("lat")
+                        .takes_value(true)
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .help("Latitude in degrees, ie 42.3555"),
+                )
+                .arg(
+                    Arg::with_name("lon")
+                        .long("lon")
+                        .takes_value(true)
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .help("Longitude in degrees, ie -71.0602"),
+                )
+                .arg(
+                    Arg::with_name("radius")
+                        .long("radius")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Furthest a station can be in kilometres"),
+                )
+                .arg(
+                    Arg::with_name("write_profile")
+                        .long("write_profile")
+                        .takes_value(true)
+                        .help("Adds a profile by this name to the config file, counting down the closest station's routes"),
+                )
+                .arg(
+                    Arg::with_name("direction")
+                        .short("d")
+                        .long("direction")
+                        .takes_value(true)
+                        .default_value("inbound")
+                        .help("Direction of the written profile"),
+                ),
+        )
+}
+
+/// Creates the transit provider from the arguments
+fn transit_provider(args: &ArgMatches) -> Result<Arc<dyn TransitProvider>, Box<dyn error::Error>> {
+    let value = |name: &str, default: &str| args.value_of(name).unwrap_or(default).to_string();
+    // times are handled in the agency timezone rather than the timezone the device is set to
+    let timezone = value("timezone", "America/New_York").parse::<Tz>()?;
+
+    // load the offline schedule, if it has been imported, to fall back on when the live feed
+    // cannot be reached
+    let gtfs_index_file = gtfs_index_file(args);
+    let schedule = if gtfs_index_file.exists() {
+        Some(GtfsIndex::load(&gtfs_index_file)?)
+    } else {
+        None
+    };
+
+    match value("provider", "mbta").as_str() {
+        "mbta" => {
+            // the catalog is kept in the cache directory unless another file is given
+            let catalog_file = args
+                .value_of("catalog")
+                .map(PathBuf::from)
+                .unwrap_or_else(default_catalog_file);
+            Ok(Arc::new(MbtaProvider::new(
+                timezone,
+                schedule,
+                catalog_file,
+            )))
+        }
+        "gtfs" => {
+            let schedule = schedule.ok_or_else(|| {
+                format!(
+                    "The gtfs provider needs a GTFS schedule.  Import one with --import_gtfs to create {}",
+                    gtfs_index_file.display()
+                )
+            })?;
+            Ok(Arc::new(GtfsProvider::new(
+                timezone,
+                schedule,
+                value("trip_updates_url", MBTA_TRIP_UPDATES_URL),
+                Some(value("alerts_url", MBTA_ALERTS_URL)),
+                Some(value("vehicle_positions_url", MBTA_VEHICLE_POSITIONS_URL)),
+            )))
+        }
+        provider => Err(format!("Unrecognized provider {}", provider).into()),
+    }
+}
+
+/// Joins the names of the codes, ie route codes to "Red Line, Orange Line".  Stops of the same
+/// station are named once
+fn names<F: Fn(&str) -> String>(codes: &[String], name: F) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for code_name in codes.iter().map(|code| name(code)) {
+        if !names.contains(&code_name) {
+            names.push(code_name);
+        }
+    }
+    names.join(", ")
+}
+
+/// Command line name of the station given by any of its names, or an error suggesting the closest
+/// names, or listing the stations when several share the name
+fn find_station<'a>(
+    catalog: &'a Catalog,
+    input: &str,
+) -> Result<&'a String, Box<dyn error::Error>> {
+    match catalog.find_stations(input).as_slice() {
+        [] => Err(format!(
+            "Unknown station {}.  {}",
+            input,
+            suggestions(catalog, input)
+        )
+        .into()),
+        [station] => Ok(station),
+        stations => Err(format!(
+            "Several stations are named {}.  Use one of: {}",
+            input,
+            stations
+                .iter()
+                .map(|station| format!("-s {}", station))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+        .into()),
+    }
+}
+
+/// The flags of the closest matches to an unknown name, or how to update when there are none
+fn suggestions(catalog: &Catalog, input: &str) -> String {
+    let flags = search(catalog, input)
+        .into_iter()
+        .take(3)
+        .map(|search_match| search_match.flag)
+        .collect::<Vec<String>>();
+    if flags.is_empty() {
+        "Update the MBTA info with -u".to_string()
+    } else {
+        format!("Did you mean {}", flags.join(" or "))
+    }
+}
+
+/// Prints the matches for the search, along with the routes at each station and their flags
+fn print_matches(catalog: &Catalog, name: &str) {
+    let matches = search(catalog, name);
+    if matches.is_empty() {
+        println!("No stations or routes match {}", name);
+    }
+    for search_match in matches {
+        println!(
+            "{} ({})  {}",
+            search_match.name, search_match.kind, search_match.flag
+        );
+        for (route_name, route_flag) in search_match.routes {
+            println!("    {}  {}", route_name, route_flag);
+        }
+    }
+}
+
+/// Prints the stations near the location given, with the flags to count down from each.  With
+/// --write_profile, the closest station is added to the config file as a profile
+fn run_nearby(
+    catalog: &Catalog,
+    nearby_args: &ArgMatches,
+    config_file: &Path,
+) -> Result<(), Box<dyn error::Error>> {
+    let latitude = nearby_args.value_of("lat").unwrap().parse::<f64>()?;
+    let longitude = nearby_args.value_of("lon").unwrap().parse::<f64>()?;
+    let radius_km = nearby_args.value_of("radius").unwrap().parse::<f64>()?;
+    // catalogs without locations, such as the built-in one, cannot find anything
+    if catalog
+        .stations
+        .values()
+        .all(|details| details.latitude.is_none())
+    {
+        return Err("The MBTA info has no station locations.  Update it with -u".into());
+    }
+    let stations = nearby(catalog, latitude, longitude, radius_km);
+    if stations.is_empty() {
+        println!("No stations within {} km", radius_km);
+    }
+    if let Some(profile) = nearby_args.value_of("write_profile") {
+        let closest = stations.first().ok_or_else(|| {
+            format!(
+                "No station within {} km to write profile {}",
+                radius_km, profile
+            )
+        })?;
+        let settings = nearby_profile(closest, nearby_args.value_of("direction").unwrap());
+        settings.validate(&format!("profile {}", profile))?;
+        append_profile(config_file, profile, &settings)?;
+        println!(
+            "Added profile {} for {} to {}",
+            profile,
+            closest.name,
+            config_file.display()
+        );
+    }
+    for station in stations {
+        println!(
+            "{} ({:.2} km)  {}",
+            station.name, station.distance_km, station.flag
+        );
+        for route in station.routes {
+            println!("    {}  {}", route.name, route.flag);
+            for (direction_flag, label) in route.directions {
+                println!(
+                    "        {} {} {}  {}",
+                    station.flag, route.flag, direction_flag, label
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Settings counting down every route at the nearby station in the direction
+fn nearby_profile(station: &NearbyStation, direction: &str) -> Settings {
+    let mut settings = Settings {
+        station: Some(vec![station.station.clone()]),
+        direction: Some(direction.to_string()),
+        ..Settings::default()
+    };
+    for route in &station.routes {
+        let (vehicle_type, line) = &route.line;
+        let lines = match vehicle_type.as_str() {
+            "Commuter_Rail" => &mut settings.commuter_rail,
+            "Ferry" => &mut settings.ferry_line,
+            "Bus" => &mut settings.bus,
+            _ => &mut settings.subway_line,
+        };
+        lines.get_or_insert_with(Vec::new).push(line.clone());
+    }
+    settings
+}
+
+/// Searches for the names given, or asks for names until an empty line when none are given
+fn run_search(catalog: &Catalog, search_args: &ArgMatches) -> Result<(), Box<dyn error::Error>> {
+    if let Some(names) = search_args.values_of("name") {
+        print_matches(catalog, &names.collect::<Vec<&str>>().join(" "));
+        return Ok(());
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("Station or route (empty to quit): ");
+        stdout().flush()?;
+        let mut name = String::new();
+        if stdin.read_line(&mut name)? == 0 || name.trim().is_empty() {
+            return Ok(());
+        }
+        print_matches(catalog, name.trim());
+        println!();
+    }
+}
+
+/// Gets the command line arguments
+pub async fn arguments() -> Result<Arguments, Box<dyn error::Error>> {
+    let args = build_app().get_matches();
+
+    // if import_gtfs is called, build the offline schedule index then exit
+    if let Some(gtfs_zip) = args.value_of("import_gtfs") {
+        println!("Importing GTFS schedule from {}", gtfs_zip);
+        let gtfs_index_file = gtfs_index_file(&args);
+        GtfsIndex::import(gtfs_zip)?.save(&gtfs_index_file)?;
+        println!(
+            "Finished importing GTFS schedule to {}",
+            gtfs_index_file.display()
+        );
+        exit(0i32);
+    }
+
+    let provider = transit_provider(&args)?;
+
+    // if write_snapshot is called, save the MBTA info for the next build then exit
+    if let Some(snapshot_file) = args.value_of("write_snapshot") {
+        println!("Writing the MBTA info snapshot to {}", snapshot_file);
+        write_snapshot(Path::new(snapshot_file)).await?;
+        println!("Finished writing the MBTA info snapshot");
+        exit(0i32);
+    }
 
     // if update_mbta is called, update mbta info then exit
     if args.is_present("update_mbta") {
         println!("Updating MBTA info");
-        mbta_countdown::mbta_info::all_mbta_info(true)?;
+        provider.catalog(true).await?;
         println!("Finished updating MBTA info");
         exit(0i32);
     }
 
-    let clock_type = match args.value_of("clock_type").unwrap() {
-        "HT16K33" => ClockType::HT16K33,
-        "TM1637" => ClockType::TM1637,
-        _ => panic!("Unrecognized clock type"),
+    // get station and vehicle conversions for the API
+    let catalog = provider.catalog(false).await?;
+
+    // if search is called, print the matching stations and routes then exit
+    if let Some(search_args) = args.subcommand_matches("search") {
+        run_search(&catalog, search_args)?;
+        exit(0i32);
+    }
+    // if nearby is called, print the closest stations then exit
+    if let Some(nearby_args) = args.subcommand_matches("nearby") {
+        run_nearby(&catalog, nearby_args, &config_file(&args))?;
+        exit(0i32);
+    }
+
+    // refresh old information in the background while counting down, used from the next start.
+    // It is refreshed quietly, as the terminal is taken over by the countdown
+    if catalog.is_stale() {
+        let provider = provider.clone();
+        tokio::spawn(async move { provider.refresh_catalog().await.ok() });
+    }
+
+    let config_file = config_file(&args);
+    let config = if config_file.exists() {
+        Some(Config::load(&config_file)?)
+    } else if let Some(profile) = args.value_of("profile") {
+        return Err(format!(
+            "Profile {} needs a config file, but there is none at {}",
+            profile,
+            config_file.display()
+        )
+        .into());
+    } else {
+        None
+    };
+    // the profile given with --profile, otherwise the config's default profile
+    let profile = args
+        .value_of("profile")
+        .map(|profile| profile.to_string())
+        .or_else(|| config.as_ref()?.default_profile.clone());
+    let profile_settings = match (&config, &profile) {
+        (Some(config), Some(profile)) => config.profile(profile)?,
+        (Some(config), None) => config.defaults.clone(),
+        _ => Settings::default(),
     };
 
-    // reforms direction input to the direction code used in the API
-    let mut dir_code = String::new();
-    if let Some(direction_input) = args.value_of("direction") {
-        match direction_input {
-            "inbound" => dir_code = "1".to_string(),
-            "outbound" => dir_code = "0".to_string(),
-            _ => panic!("Unknown direction input"),
+    // settings from the command line override the profile, which overrides the config defaults
+    let cli = cli_settings(&args)?;
+    let settings = cli.clone().or(&profile_settings).or(&Settings::builtin());
+    settings.validate("the settings")?;
+    let default = countdown(provider.as_ref(), &catalog, &settings, profile).await?;
+
+    // the scheduled profiles are switched to while running, unless a profile was chosen
+    let config = config.filter(|config| !config.schedule.is_empty() && !args.is_present("profile"));
+    let mut scheduled = BTreeMap::new();
+    for scheduled_profile in config.iter().flat_map(|config| &config.schedule) {
+        let name = &scheduled_profile.profile;
+        if scheduled.contains_key(name) {
+            continue;
         }
+        let settings = cli
+            .clone()
+            .or(&config.as_ref().unwrap().profile(name)?)
+            .or(&Settings::builtin());
+        let source = format!("profile {}", name);
+        settings.validate(&source)?;
+        let countdown = countdown(provider.as_ref(), &catalog, &settings, Some(name.clone()))
+            .await
+            .map_err(|err| format!("Profile {}: {}", name, err))?;
+        scheduled.insert(name.clone(), countdown);
+    }
+
+    let clock_type = match settings.clock_type.as_deref() {
+        Some("TM1637") => ClockType::TM1637,
+        _ => ClockType::HT16K33,
     };
+    Ok(Arguments {
+        profiles: Profiles {
+            default,
+            scheduled,
+            config,
+        },
+        hardware: Hardware {
+            clock_type,
+            clock_brightness: settings.clock_brightness.unwrap(),
+            clock_address: settings.clock_address.unwrap(),
+            screen_address: settings.screen_address.unwrap(),
+            tm1637_pins: (
+                settings.tm1637_clock_pin.unwrap(),
+                settings.tm1637_data_pin.unwrap(),
+            ),
+            shutdown_pin: settings.shutdown_pin.unwrap(),
+            min_poll_secs: settings.min_poll_secs.unwrap(),
+            max_poll_secs: settings.max_poll_secs.unwrap(),
+        },
+        provider,
+    })
+}
 
-    // Convert either commuter_rail or subway_line to MBTA API vehicle code
-    let mut vehicle_code = String::new();
-    if let Some(commuter_input) = args.value_of("commuter_rail") {
-        vehicle_code = commuter_rails.get(commuter_input).unwrap().to_owned();
-    } else if let Some(subway) = args.value_of("subway_line") {
-        vehicle_code = subway_lines.get(subway).unwrap().to_owned();
-    } else if let Some(ferry) = args.value_of("ferry_line") {
-        vehicle_code = ferry_lines.get(ferry).unwrap().to_owned()
+/// The settings given on the command line
+fn cli_settings(args: &ArgMatches) -> Result<Settings, Box<dyn error::Error>> {
+    let values = |name: &str| {
+        args.values_of(name).map(|values| {
+            values
+                .map(|value| value.to_string())
+                .collect::<Vec<String>>()
+        })
     };
+    let value = |name: &str| args.value_of(name).map(|value| value.to_string());
+    Ok(Settings {
+        station: values("station"),
+        stop_number: values("stop_number"),
+        to: value("to"),
+        commuter_rail: values("commuter_rail"),
+        subway_line: values("subway_line"),
+        ferry_line: values("ferry_line"),
+        bus: values("bus"),
+        direction: value("direction"),
+        walk_min: value("walk_min")
+            .map(|walk_min| walk_min.parse())
+            .transpose()?,
+        late_threshold: value("late_threshold")
+            .map(|late_threshold| late_threshold.parse())
+            .transpose()?,
+        // a flag can only turn skipping on, so a profile skipping full trains is kept otherwise
+        skip_full: Some(true).filter(|_| args.is_present("skip_full")),
+        time_type: value("time_type"),
+        clock_type: value("clock_type"),
+        clock_brightness: value("clock_brightness")
+            .map(|clock_brightness| clock_brightness.parse())
+            .transpose()?,
+        ..Settings::default()
+    })
+}
 
-    // Convert station to API code and check if the vehicle code exists at the station
-    let mut station = String::new();
-    if let Some(station_input) = args.value_of("station") {
-        let station_hashmap = station_info.get(station_input).unwrap();
-        station = station_hashmap.keys().last().unwrap().to_owned();
-        let stopping = station_hashmap.get(&station).unwrap();
-        if !stopping.contains(&vehicle_code) {
-            panic!(
-                "{} not at {}\nStopping at {}: {:?}",
-                vehicle_code, station, station, stopping
+/// The config file given with --config, or the default one
+fn config_file(args: &ArgMatches) -> PathBuf {
+    args.value_of("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_config_file)
+}
+
+/// The offline schedule index given with --gtfs_index, or the default one
+fn gtfs_index_file(args: &ArgMatches) -> PathBuf {
+    args.value_of("gtfs_index")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_gtfs_index_file)
+}
+
+/// Converts the settings into the API codes to count down, checking the routes stop at the
+/// stations, along with the names to display
+async fn countdown(
+    provider: &dyn TransitProvider,
+    catalog: &Catalog,
+    settings: &Settings,
+    profile: Option<String>,
+) -> Result<Countdown, Box<dyn error::Error>> {
+    let time_type = match settings.time_type.as_deref() {
+        Some("arrival") => TimeType::Arrival,
+        Some("departure") => TimeType::Departure,
+        _ => TimeType::Best,
+    };
+
+    // Convert the commuter_rail, subway_line, ferry_line and bus inputs to MBTA API vehicle codes.
+    // Any number of each can be given to merge them into one countdown
+    let mut vehicle_codes = Vec::new();
+    for (line_arg, vehicle_type, lines) in [
+        ("commuter_rail", "Commuter_Rail", &settings.commuter_rail),
+        ("subway_line", "Subway", &settings.subway_line),
+        ("ferry_line", "Ferry", &settings.ferry_line),
+        ("bus", "Bus", &settings.bus),
+    ] {
+        for line_input in lines.iter().flatten() {
+            let vehicle_code = catalog
+                .find_route(vehicle_type, line_input)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown {} {}.  {}",
+                        line_arg,
+                        line_input,
+                        suggestions(catalog, line_input)
+                    )
+                })?;
+            // the same line may be given more than once
+            if !vehicle_codes.iter().any(|code| code == vehicle_code) {
+                vehicle_codes.push(vehicle_code.to_owned());
+            }
+        }
+    }
+    if vehicle_codes.is_empty() {
+        return Err("No line given.  Set commuter_rail, subway_line, ferry_line or bus in the profile, or use -c, -l, -f or --bus".into());
+    }
+
+    // reforms direction input to the direction id used in the API
+    let direction_input = settings
+        .direction
+        .as_deref()
+        .ok_or("No direction given.  Set direction in the profile or use -d")?;
+    let direction_id = catalog
+        .find_direction(&vehicle_codes, direction_input)
+        .ok_or_else(|| {
+            let known = vehicle_codes
+                .iter()
+                .flat_map(|route| (0..2).map(move |direction_id| (route, direction_id)))
+                .map(|(route, direction_id)| catalog.direction_label(route, direction_id))
+                .collect::<Vec<String>>();
+            format!(
+                "Unknown direction {}.  Use inbound, outbound or one of: {}",
+                direction_input,
+                known.join(", ")
+            )
+        })?;
+    let dir_code = direction_id.to_string();
+
+    // Convert stations to API codes and check if each vehicle code exists at one of the stations.
+    // A station with several stops uses each one served by the routes
+    let mut stations = Vec::new();
+    for station_input in settings.station.iter().flatten() {
+        let station = find_station(catalog, station_input)?;
+        stations.extend(catalog.station_stops(station, &vehicle_codes));
+    }
+    // stop numbers of platforms, ie 70001, are checked with their station, using the catalog when
+    // it has the platform.  Bus stop numbers are the API codes, and as the catalog only has
+    // stations, they and any other platforms are looked up with the routes serving them
+    let stop_numbers = settings.stop_number.clone().unwrap_or_default();
+    let mut stopping_at_stops = Vec::new();
+    // stop number -> name of the station or bus stop
+    let mut stop_names = HashMap::new();
+    for stop_number in &stop_numbers {
+        let stop = match catalog.platform(stop_number) {
+            Some(platform) => platform,
+            None => provider
+                .stop(stop_number)
+                .await
+                .map_err(|err| format!("Unable to look up stop number {}: {}", stop_number, err))?
+                .ok_or_else(|| format!("Unknown stop number {}", stop_number))?,
+        };
+        let name = match &stop.parent_station {
+            Some(parent_station) => catalog.station_name(parent_station),
+            None => stop.name,
+        };
+        stop_names.insert(stop_number.clone(), name);
+        stopping_at_stops.extend(stop.routes);
+    }
+    let mut stopping = catalog.routes_at(&stations);
+    for route in stopping_at_stops {
+        if !stopping.contains(&route) {
+            stopping.push(route);
+        }
+    }
+    stations.extend(stop_numbers.iter().cloned());
+    if stations.is_empty() {
+        return Err("No station given.  Set station or stop_number in the profile, or use -s or --stop_number".into());
+    }
+    // the names riders know the routes and stations by, for messages and the displays
+    let route_names = names(&vehicle_codes, |route| catalog.route_name(route));
+    let station_names = names(&stations, |station| match stop_names.get(station) {
+        Some(name) => name.clone(),
+        None => catalog.station_name(station),
+    });
+    for vehicle_code in &vehicle_codes {
+        if !stopping.contains(vehicle_code) {
+            return Err(format!(
+                "{} not at {}\nStopping at {}: {}",
+                catalog.route_name(vehicle_code),
+                station_names,
+                station_names,
+                names(&stopping, |route| catalog.route_name(route))
             )
+            .into());
         }
-    };
+    }
 
-    // either set clock_brightness to input or defaul to 7
-    let clock_brightness = args.value_of("clock_brightness").unwrap().parse::<u8>()?;
-    // exit if the brightness is too high for TM1637
-    if clock_brightness > 7 && clock_type == ClockType::TM1637 {
-        panic!(
-            "Clock brightness limit of 7 for TM1637.  Value input is {}",
-            clock_brightness
-        );
-    };
-    // exit if the brightness is too high for HT16K33
-    if clock_brightness > 9 && clock_type == ClockType::HT16K33 {
-        panic!(
-            "Clock brightness limit of 9 for HT16K33.  Value input is {}",
-            clock_brightness
-        );
+    // Convert the destination to its API codes and check that one of the vehicles stops there.
+    // Trains stopping at any of the destination's stops served by the routes are counted down
+    let mut destination = Vec::new();
+    let mut destination_name = None;
+    if let Some(destination_input) = &settings.to {
+        let destination_station = find_station(catalog, destination_input)?;
+        destination = catalog.station_stops(destination_station, &vehicle_codes);
+        if destination.is_empty() {
+            return Err(format!("No stops known at {}", destination_input).into());
+        }
+        let name = names(&destination, |destination| {
+            catalog.station_name(destination)
+        });
+        let stopping = catalog.routes_at(&destination);
+        if !vehicle_codes
+            .iter()
+            .any(|vehicle_code| stopping.contains(vehicle_code))
+        {
+            return Err(format!(
+                "{} not at {}\nStopping at {}: {}",
+                route_names,
+                name,
+                name,
+                names(&stopping, |route| catalog.route_name(route))
+            )
+            .into());
+        }
+        destination_name = Some(name);
     };
-    Ok((
-        dir_code,
-        station,
-        clock_brightness,
-        vehicle_code,
-        clock_type,
-    ))
+
+    // how riders know the direction on each route, ie "Southbound to Ashmont/Braintree"
+    let mut direction_labels = vehicle_codes
+        .iter()
+        .map(|route| catalog.direction_label(route, direction_id))
+        .collect::<Vec<String>>();
+    direction_labels.dedup();
+    let direction_label = direction_labels.join(", ");
+    let heading = format!("{} at {}, {}", route_names, station_names, direction_label);
+    Ok(Countdown {
+        profile,
+        query: Query {
+            route_codes: vehicle_codes,
+            stations,
+            destination,
+            skip_full: settings.skip_full.unwrap_or(false),
+            dir_code,
+            time_type,
+        },
+        heading,
+        destination_name,
+        direction_label,
+        walk_min: settings.walk_min.unwrap_or(0),
+        late_threshold_min: settings.late_threshold.unwrap_or(0),
+    })
 }
@@ -1,338 +1,1240 @@
-use rayon::{prelude::*, ThreadPoolBuilder};
+use crate::transit_provider::Stop;
+use chrono::{DateTime, Utc};
 use reqwest;
-use scraper::{Html, Selector};
-use serde_json;
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
     collections::HashMap,
+    env, fs,
     fs::File,
-    io::{BufReader, BufWriter},
-    path::Path,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-/// Scrapes MBTA station and vehicle info from their website then stores the information in JSON files and returns in HashMaps
+/// Vehicle type -> common line name -> MBTA API route code
+pub type VehicleInfo = HashMap<String, HashMap<String, String>>;
+/// Station name -> MBTA API station code -> route codes stopping at the station
+pub type StationInfo = HashMap<String, HashMap<String, Vec<String>>>;
+
+/// Environment variable with an optional MBTA API key, which raises the API rate limit
+pub const MBTA_API_KEY_VAR: &str = "MBTA_API_KEY";
+/// Source recorded in catalogs retrieved from the MBTA V3 API
+const MBTA_API_SOURCE: &str = "https://api-v3.mbta.com";
+/// Attempts at each catalog request before giving up on it
+const CATALOG_ATTEMPTS: u32 = 4;
+/// Wait before the first retry of a catalog request, doubled after each further failure
+const CATALOG_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Version of the catalog file format, increased whenever the format changes so older files are
+/// refreshed rather than misread
+pub const CATALOG_SCHEMA_VERSION: u32 = 1;
+/// Legacy files the catalog was saved to in the working directory before it was versioned
+const LEGACY_VEHICLE_FILE: &str = "mbta_vehicle_info.json";
+const LEGACY_STATION_FILE: &str = "mbta_station_info.json";
+const LEGACY_DETAILS_FILE: &str = "mbta_catalog_details.json";
+/// Catalog built into the program, used until one is retrieved from the MBTA API
+const CATALOG_SNAPSHOT: &str = include_str!("../data/catalog_snapshot.json");
+/// Days before a catalog is refreshed in the background
+const CATALOG_MAX_AGE_DAYS: i64 = 30;
+
+/// Everything known about the routes and stations, used to convert command line input to API codes
+#[derive(Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub schema_version: u32,
+    /// When the catalog was retrieved, if known
+    pub created: Option<DateTime<Utc>>,
+    /// Where the catalog was retrieved from, ie the MBTA V3 API
+    pub source: String,
+    pub vehicle_info: VehicleInfo,
+    pub station_info: StationInfo,
+    /// API route code -> route details
+    pub routes: HashMap<String, RouteDetails>,
+    /// API parent station code -> station details, including the child platforms of the station
+    pub stations: HashMap<String, StationDetails>,
+}
+
+/// Route information beyond the name and code
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RouteDetails {
+    pub name: String,
+    /// GTFS route type, ie 2 for commuter rail
+    pub route_type: u8,
+    /// Direction names by direction id, ie ["Outbound", "Inbound"]
+    pub direction_names: Vec<String>,
+    /// Final destinations by direction id, ie ["Needham Heights", "South Station"]
+    pub direction_destinations: Vec<String>,
+    /// Other names the route is known by, ie "B" for the Green Line B branch
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Station information beyond the name and code
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StationDetails {
+    pub name: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// GTFS wheelchair boarding, 0 no information, 1 accessible, 2 not accessible
+    pub wheelchair_boarding: u8,
+    /// API codes of the platforms within the station
+    pub child_stops: Vec<String>,
+    /// Other names the station is known by, ie "JFK" for JFK/UMass
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Route and station details saved alongside the legacy vehicle and station information
+#[derive(Default, Serialize, Deserialize)]
+struct CatalogDetails {
+    routes: HashMap<String, RouteDetails>,
+    stations: HashMap<String, StationDetails>,
+}
+
+/// Default location of the catalog, in the user's cache directory, ie
+/// ~/.cache/mbta_countdown/catalog.json, or the working directory when there is none
+pub fn default_catalog_file() -> PathBuf {
+    match dirs::cache_dir() {
+        Some(cache_dir) => cache_dir.join("mbta_countdown").join("catalog.json"),
+        None => PathBuf::from("mbta_catalog.json"),
+    }
+}
+
+/// Retrieves MBTA route and station info from the MBTA V3 API then stores the information in the
+/// catalog file and returns it.  Without an update or a saved catalog, the snapshot built into
+/// the program is returned so it can start without a connection
 ///
 /// # Arguments
 ///
-///  * `update` - a boolean on whether or not to force an update from the MBTA website
+///  * `update` - a boolean on whether or not to force an update from the MBTA API
+///  * `catalog_file` - where the catalog is saved, ie from `default_catalog_file`
 ///
 ///  # Examples
 ///
+///  ```no_run
+///  use mbta_countdown::mbta_info::{all_mbta_info, default_catalog_file};
+///  # async fn example() {
+///  let catalog = all_mbta_info(false, &default_catalog_file()).await.unwrap();
+///  # }
 ///  ```
-///  use mbta_countdown::mbta_info::all_mbta_info
-///  let (vehicle_info, station_info) = all_mbta_info()?
-///  ```
-pub fn all_mbta_info(
+pub async fn all_mbta_info(
     update: bool,
-) -> Result<
-    (
-        HashMap<String, HashMap<String, String>>,
-        HashMap<String, HashMap<String, Vec<String>>>,
-    ),
-    Box<dyn std::error::Error>,
-> {
-    // setup file names of the JSON files for saving or loading
-    let mbta_vehicle_file_loc = "mbta_vehicle_info.json";
-    let mbta_station_file_loc = "mbta_station_info.json";
-
-    // if mbta vehicle JSON exists or update not called, read the JSON
-    if !Path::new(&mbta_vehicle_file_loc).exists() | update {
-        println!("Updating vehicle information");
-
-        let vehicle_info_mutex = Arc::new(Mutex::new(HashMap::new()));
-        let vehicle_clone_outer = Arc::clone(&vehicle_info_mutex);
-        let pool = ThreadPoolBuilder::new()
-            .num_threads(3)
-            .build()
-            .expect("Threadpool failure");
-        pool.scope(move |s| {
-            let vehicle_clone_1 = Arc::clone(&vehicle_clone_outer);
-            s.spawn(move |_s| {
-                let commuter_info =
-                    retrieve_commuter().unwrap_or_else(|err| panic!("Error: {}", err));
-                let mut vehicle_info_unlocked = vehicle_clone_1.lock().unwrap();
-                vehicle_info_unlocked.insert("Commuter_Rail".to_string(), commuter_info);
-            });
-            let vehicle_clone_2 = Arc::clone(&vehicle_clone_outer);
-            s.spawn(move |_s| {
-                let subway_info = retrieve_subway().unwrap_or_else(|err| panic!("Error: {}", err));
-                let mut vehicle_info_unlocked = vehicle_clone_2.lock().unwrap();
-                vehicle_info_unlocked.insert("Subway".to_string(), subway_info);
-            });
-            let vehicle_clone_3 = Arc::clone(&vehicle_clone_outer);
-            s.spawn(move |_s| {
-                let ferry_info = retrieve_ferry().unwrap_or_else(|err| panic!("Error: {}", err));
-                let mut vehicle_info_unlocked = vehicle_clone_3.lock().unwrap();
-                vehicle_info_unlocked.insert("Ferry".to_string(), ferry_info);
-            });
-        });
+    catalog_file: &Path,
+) -> Result<Catalog, Box<dyn std::error::Error>> {
+    let mut previous = read_catalog(catalog_file, true)?;
+    for legacy_dir in legacy_dirs() {
+        if previous.is_some() {
+            break;
+        }
+        previous = migrate_legacy_catalog(catalog_file, &legacy_dir)?;
+    }
+    if update {
+        println!("Updating MBTA route and station information");
+        // stations missing from the update are kept from the snapshot when nothing is saved
+        let previous = match previous {
+            Some(previous) => previous,
+            None => snapshot_catalog()?,
+        };
+        return update_catalog(catalog_file, previous, true).await;
+    }
+    match previous {
+        Some(previous) => {
+            println!(
+                "Using existing MBTA route and station information from {}",
+                catalog_file.display()
+            );
+            Ok(previous)
+        }
+        None => {
+            println!("Using the built-in MBTA route and station information");
+            snapshot_catalog()
+        }
+    }
+}
 
-        let f = File::create(&mbta_vehicle_file_loc)?;
-        let bw = BufWriter::new(f);
-        let vehicle_info = Arc::try_unwrap(vehicle_info_mutex).unwrap().into_inner()?;
-        serde_json::to_writer(bw, &vehicle_info)?;
-    } else {
-        println!("Using existing vehicle information")
+/// Updates the catalog file from the MBTA API without printing its progress, for refreshing a
+/// stale catalog while the countdown is running
+pub async fn refresh_mbta_info(catalog_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let previous = match read_catalog(catalog_file, false)? {
+        Some(previous) => previous,
+        None => snapshot_catalog()?,
     };
-    let g = File::open(&mbta_vehicle_file_loc)?;
-    let reader = BufReader::new(g);
-    let vehicle_info = serde_json::from_reader(reader)?;
-
-    // if mbta station JSON exists or update not called, read the JSON
-    if !Path::new(&mbta_station_file_loc).exists() | update {
-        println!("Updating station information");
-        // otherwise scrape all data from the website
-        let station_info_to_write = retrieve_stations()?;
-        let f = File::create(&mbta_station_file_loc)?;
-        let bw = BufWriter::new(f);
-        serde_json::to_writer(bw, &station_info_to_write)?;
+    update_catalog(catalog_file, previous, false).await?;
+    Ok(())
+}
+
+/// The catalog built into the program, from data/catalog_snapshot.json.  Update the snapshot with
+/// `write_snapshot`, run by `--write_snapshot data/catalog_snapshot.json`
+pub fn snapshot_catalog() -> Result<Catalog, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(CATALOG_SNAPSHOT)?)
+}
+
+/// Retrieves the catalog from the MBTA API and saves it as the snapshot built into the program.
+/// Nothing is saved when any route's stations cannot be retrieved, so the snapshot is complete
+pub async fn write_snapshot(snapshot_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (catalog, failed_routes) = retrieve_catalog(true).await?;
+    if !failed_routes.is_empty() {
+        let failed = failed_routes
+            .iter()
+            .map(|(route_code, err)| format!("{} - {}", route_code, err))
+            .collect::<Vec<String>>();
+        return Err(format!(
+            "Could not retrieve the stations of every route, the snapshot is unchanged:\n  {}",
+            failed.join("\n  ")
+        )
+        .into());
+    }
+    // converted to a JSON value first so the keys are sorted, keeping the diffs of the checked in
+    // snapshot small
+    let mut bw = BufWriter::new(File::create(snapshot_file)?);
+    serde_json::to_writer_pretty(&mut bw, &serde_json::to_value(&catalog)?)?;
+    bw.write_all(b"\n")?;
+    Ok(())
+}
+
+impl Catalog {
+    /// Whether the catalog is old enough, or of unknown age, that it should be refreshed
+    pub fn is_stale(&self) -> bool {
+        self.created.is_none_or(|created| {
+            Utc::now() - created > chrono::Duration::days(CATALOG_MAX_AGE_DAYS)
+        })
+    }
+}
+
+/// Retrieves the catalog from the MBTA API, keeping the stations of the previous catalog for any
+/// routes that could not be retrieved, then saves it.  The previous catalog is returned when the
+/// routes cannot be retrieved at all
+async fn update_catalog(
+    catalog_file: &Path,
+    previous: Catalog,
+    report: bool,
+) -> Result<Catalog, Box<dyn std::error::Error>> {
+    let (mut catalog, failed_routes) = match retrieve_catalog(report).await {
+        Ok(retrieved) => retrieved,
+        Err(err) => {
+            if report {
+                println!(
+                    "Could not refresh the MBTA information, keeping the previous information: {}",
+                    err
+                );
+            }
+            return Ok(previous);
+        }
+    };
+    keep_previous_stations(&mut catalog, &previous, &failed_routes);
+    write_catalog(catalog_file, &catalog)?;
+    if !report {
+        return Ok(catalog);
+    }
+    // report the routes whose stations could not be refreshed
+    if failed_routes.is_empty() {
+        println!("Refreshed all routes and stations");
     } else {
-        println!("Using existing station information")
+        println!(
+            "Could not refresh the stations of {} routes, keeping their previous stations:",
+            failed_routes.len()
+        );
+        for (route_code, err) in &failed_routes {
+            println!("  {} - {}", route_code, err);
+        }
+    }
+    Ok(catalog)
+}
+
+/// Reads the catalog file, or None when there is no file or it was saved in an older version of
+/// the format.  A file saved in a newer format is an error rather than being overwritten.  An
+/// older file is reported when `report` is set
+fn read_catalog(
+    catalog_file: &Path,
+    report: bool,
+) -> Result<Option<Catalog>, Box<dyn std::error::Error>> {
+    if !catalog_file.exists() {
+        return Ok(None);
+    }
+    // only the version is read at first, as the rest of an older format may not parse
+    let version: Value = read_json(catalog_file)?;
+    match version["schema_version"].as_u64() {
+        Some(version) if version == CATALOG_SCHEMA_VERSION as u64 => {
+            Ok(Some(read_json(catalog_file)?))
+        }
+        Some(version) if version > CATALOG_SCHEMA_VERSION as u64 => Err(format!(
+            "{} was saved by a newer version of the program in format {}, while this one reads format {}.  Update the program or use another file with --catalog",
+            catalog_file.display(),
+            version,
+            CATALOG_SCHEMA_VERSION
+        )
+        .into()),
+        _ => {
+            if report {
+                println!(
+                    "{} was saved in an older format and will be refreshed",
+                    catalog_file.display()
+                );
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Saves the catalog, creating its directory if needed
+fn write_catalog(catalog_file: &Path, catalog: &Catalog) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(catalog_dir) = catalog_file.parent() {
+        fs::create_dir_all(catalog_dir)?;
+    }
+    write_json(catalog_file, catalog)
+}
+
+/// Directories earlier versions may have saved their JSON files in: the working directory they
+/// were run from, or the directory of the program when run from elsewhere
+fn legacy_dirs() -> Vec<PathBuf> {
+    let mut legacy_dirs = vec![PathBuf::from(".")];
+    if let Some(program_dir) = env::current_exe()
+        .ok()
+        .and_then(|program| program.parent().map(Path::to_path_buf))
+    {
+        legacy_dirs.push(program_dir);
+    }
+    legacy_dirs
+}
+
+/// Converts the JSON files saved in `legacy_dir` by earlier versions into the catalog file,
+/// returning the converted catalog.  None when there are no legacy files
+fn migrate_legacy_catalog(
+    catalog_file: &Path,
+    legacy_dir: &Path,
+) -> Result<Option<Catalog>, Box<dyn std::error::Error>> {
+    let vehicle_file = legacy_dir.join(LEGACY_VEHICLE_FILE);
+    let station_file = legacy_dir.join(LEGACY_STATION_FILE);
+    if !vehicle_file.exists() || !station_file.exists() {
+        return Ok(None);
+    }
+    println!(
+        "Moving the MBTA route and station information from {} and {} to {}",
+        vehicle_file.display(),
+        station_file.display(),
+        catalog_file.display()
+    );
+    // files saved before the details were added only have the vehicles and stations
+    let details_file = legacy_dir.join(LEGACY_DETAILS_FILE);
+    let details: CatalogDetails = if details_file.exists() {
+        read_json(&details_file)?
+    } else {
+        CatalogDetails::default()
     };
-    let g = File::open(&mbta_station_file_loc)?;
-    let reader = BufReader::new(g);
-    let station_info = serde_json::from_reader(reader)?;
-    Ok((vehicle_info, station_info))
-}
-
-/// Scrapes all station information from the MBTA websites and returns a HashMap of the information
-fn retrieve_stations(
-) -> Result<HashMap<String, HashMap<String, Vec<String>>>, Box<dyn std::error::Error>> {
-    // Setup the urls for subway, commueter rail, and ferry
-    let subway_url = "https://www.mbta.com/stops/subway#subway-tab";
-    let communter_url = "https://www.mbta.com/stops/commuter-rail#commuter-rail-tab";
-    let ferry_url = "https://www.mbta.com/stops/ferry#ferry-tab";
-
-    // Parse the urls for the station information and add to the hashmap
-    let mut station_conversion = HashMap::new();
-    station_conversion = update_station_hashmap(station_conversion, parse_stations(subway_url)?);
-    station_conversion = update_station_hashmap(station_conversion, parse_stations(communter_url)?);
-    station_conversion = update_station_hashmap(station_conversion, parse_stations(ferry_url)?);
-    Ok(station_conversion)
-}
-
-fn update_station_hashmap(
-    mut station_conversion: HashMap<String, HashMap<String, Vec<String>>>,
-    new_stations_info: Vec<(String, String, Vec<String>)>,
-) -> HashMap<String, HashMap<String, Vec<String>>> {
-    for (station, station_api, vehicles) in new_stations_info {
-        let mut api_veh = HashMap::new();
-        api_veh.insert(station_api, vehicles);
-        station_conversion.insert(station, api_veh);
-    }
-    station_conversion
-}
-
-/// Pulls the station information along with vehicles that stop at the station from the given URL
-fn parse_stations(
-    url: &str,
-) -> Result<Vec<(String, String, Vec<String>)>, Box<dyn std::error::Error>> {
-    // get the website text
-    let website_text = reqwest::blocking::get(url)?.text()?;
-
-    // find all relevent buttons that contain the station information
-    let document = Html::parse_document(&website_text);
-    let button_selector =
-        Selector::parse(r#"a[class="btn button stop-btn m-detailed-stop"]"#).unwrap();
-    let buttons = document.select(&button_selector);
-
-    // iterate on buttons and pull out the station information
-    // Rayon threads connot pass iterated buttons, so this is done beforehand
-    let station_api: Vec<(String, String)> = buttons
-        .map(|button| {
-            let station_name = button
-                .value()
-                .attr("data-name")
-                .unwrap()
-                .replace(" ", "_")
-                .replace("'", "");
-            let station_api_name = button.value().attr("href").unwrap().replace("/stops/", "");
-            (station_name, station_api_name)
-        })
-        .collect();
+    let catalog = Catalog {
+        schema_version: CATALOG_SCHEMA_VERSION,
+        // the legacy files were written when they were retrieved
+        created: fs::metadata(&station_file)?
+            .modified()
+            .ok()
+            .map(DateTime::<Utc>::from),
+        source: format!("{} (legacy files)", MBTA_API_SOURCE),
+        vehicle_info: read_json(&vehicle_file)?,
+        station_info: read_json(&station_file)?,
+        routes: details.routes,
+        stations: details.stations,
+    };
+    write_catalog(catalog_file, &catalog)?;
+    for legacy_file in &[vehicle_file, station_file, details_file] {
+        if legacy_file.exists() {
+            fs::remove_file(legacy_file)?;
+        }
+    }
+    Ok(Some(catalog))
+}
 
-    // create new vector to put parallel results into
-    let mut station_info_all = Vec::new();
-
-    // add station vehicles through rayon threads with par_iter
-    station_api
-        .par_iter()
-        .map(|(station_name, station_api_name)| {
-            (
-                station_name.clone(),
-                station_api_name.clone(),
-                station_vehicles(station_api_name)
-                    .unwrap_or_else(|err| panic!("Station vehicle error: {}", err)),
-            )
-        })
-        .collect_into_vec(&mut station_info_all);
-
-    Ok(station_info_all)
-}
-
-/// Finds all vehicles that stop at the station of interest
-fn station_vehicles(station_code: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    println!("Retrieving info for station: {}", station_code);
-    // get the website text for the station
-    let station_url = format!("https://www.mbta.com/stops/{}", station_code);
-    let website_text = reqwest::blocking::get(&station_url)?.text()?;
-
-    // parse and find the buttons which contain the vehicles that stop at the station
-    let document = Html::parse_document(&website_text);
-    let button_selector = Selector::parse(r#"a[class="c-link-block__outer-link"]"#).unwrap();
-    let vehicle_buttons = document.select(&button_selector);
-
-    // pull out vehicle codes from the buttons and place into a vec
-    let vehicles: Vec<String> = vehicle_buttons
-        .map(|button| {
-            button
-                .value()
-                .attr("href")
-                .unwrap()
-                .replace("/schedules/", "")
+/// Copies the stations of the routes that could not be refreshed from the previous catalog
+fn keep_previous_stations(
+    catalog: &mut Catalog,
+    previous: &Catalog,
+    failed_routes: &[(String, String)],
+) {
+    for (route_code, _) in failed_routes {
+        for (station_name, station_codes) in &previous.station_info {
+            for (station_code, routes) in station_codes {
+                if !routes.contains(route_code) {
+                    continue;
+                }
+                let new_routes = catalog
+                    .station_info
+                    .entry(station_name.clone())
+                    .or_default()
+                    .entry(station_code.clone())
+                    .or_default();
+                if !new_routes.contains(route_code) {
+                    new_routes.push(route_code.clone());
+                }
+                if let Some(details) = previous.stations.get(station_code) {
+                    catalog
+                        .stations
+                        .entry(station_code.clone())
+                        .or_insert_with(|| details.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Other names for a station or route made of several names, ie JFK and UMass for "JFK/UMass"
+pub fn name_aliases(name: &str) -> Vec<String> {
+    let parts = name
+        .split('/')
+        .map(|part| part.trim())
+        .collect::<Vec<&str>>();
+    if parts.len() < 2 {
+        return Vec::new();
+    }
+    parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// The lowercase letters and digits of a name, so that names match whatever their case, spacing
+/// or punctuation, ie "Forest Hills", "forest_hills" and "FOREST-HILLS"
+pub fn name_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+impl Catalog {
+    /// API code of the route of the vehicle type, ie Commuter_Rail, given by its command line
+    /// name, display name, API code or one of its aliases, ignoring case
+    pub fn find_route(&self, vehicle_type: &str, input: &str) -> Option<&String> {
+        let lines = self.vehicle_info.get(vehicle_type)?;
+        if let Some(route_code) = lines.get(input) {
+            return Some(route_code);
+        }
+        let input = name_key(input);
+        lines
+            .iter()
+            .find(|(line, route_code)| {
+                name_key(line) == input
+                    || name_key(route_code) == input
+                    || self.routes.get(*route_code).is_some_and(|details| {
+                        name_key(&details.name) == input
+                            || details.aliases.iter().any(|alias| name_key(alias) == input)
+                    })
+            })
+            .map(|(_, route_code)| route_code)
+    }
+
+    /// Command line names of the stations, as used in the station information, given by that
+    /// name, display name, API code, one of its aliases or the API code of one of its platforms,
+    /// ignoring case.  Sorted, as an alias can be shared by several stations
+    pub fn find_stations(&self, input: &str) -> Vec<&String> {
+        if let Some((station, _)) = self.station_info.get_key_value(input) {
+            return vec![station];
+        }
+        let input = name_key(input);
+        let mut stations = self
+            .station_info
+            .iter()
+            .filter(|(station, station_codes)| {
+                name_key(station) == input
+                    || station_codes.keys().any(|station_code| {
+                        let details = self.stations.get(station_code);
+                        name_key(station_code) == input
+                            || details.is_some_and(|details| {
+                                name_key(&details.name) == input
+                                    || details.aliases.iter().any(|alias| name_key(alias) == input)
+                                    || details
+                                        .child_stops
+                                        .iter()
+                                        .any(|child| name_key(child) == input)
+                            })
+                    })
+            })
+            .map(|(station, _)| station)
+            .collect::<Vec<&String>>();
+        stations.sort();
+        stations
+    }
+
+    /// API codes of the parent stations sharing the command line name that are served by any of
+    /// the routes, sorted so the same stops are used every time.  Names such as a hub with
+    /// separate stations for each line have several.  All of them are returned when none are
+    /// served by the routes, so the routes that do stop there can be reported
+    pub fn station_stops(&self, station: &str, route_codes: &[String]) -> Vec<String> {
+        let station_codes = match self.station_info.get(station) {
+            Some(station_codes) => station_codes,
+            None => return Vec::new(),
+        };
+        let mut all_stops = station_codes.keys().cloned().collect::<Vec<String>>();
+        all_stops.sort_unstable();
+        let served_stops = all_stops
+            .iter()
+            .filter(|station_code| {
+                station_codes[*station_code]
+                    .iter()
+                    .any(|route| route_codes.contains(route))
+            })
+            .cloned()
+            .collect::<Vec<String>>();
+        if served_stops.is_empty() {
+            all_stops
+        } else {
+            served_stops
+        }
+    }
+
+    /// Routes stopping at any of the parent stations, sorted and without repeats
+    pub fn routes_at(&self, station_codes: &[String]) -> Vec<String> {
+        let mut routes = self
+            .station_info
+            .values()
+            .flat_map(|codes| codes.iter())
+            .filter(|(station_code, _)| station_codes.contains(station_code))
+            .flat_map(|(_, routes)| routes.iter().cloned())
+            .collect::<Vec<String>>();
+        routes.sort_unstable();
+        routes.dedup();
+        routes
+    }
+
+    /// API direction id, 0 or 1, of the direction given as inbound or outbound, the id itself, or
+    /// by the direction name or destination of any of the routes, ie "Southbound" or "Ashmont"
+    /// on the Red Line, ignoring case
+    pub fn find_direction(&self, route_codes: &[String], input: &str) -> Option<u8> {
+        let input = name_key(input);
+        match input.as_str() {
+            "0" | "outbound" => return Some(0),
+            "1" | "inbound" => return Some(1),
+            _ => (),
+        }
+        route_codes
+            .iter()
+            .filter_map(|route_code| self.routes.get(route_code))
+            .find_map(|details| {
+                (0..2u8).find(|direction_id| {
+                    let direction_id = *direction_id as usize;
+                    let name = details.direction_names.get(direction_id);
+                    let destination = details.direction_destinations.get(direction_id);
+                    name.is_some_and(|name| {
+                        !name.is_empty()
+                            && (name_key(name) == input || name_key(&direction_name(name)) == input)
+                    }) || destination.is_some_and(|destination| {
+                        !destination.is_empty()
+                            && (name_key(destination) == input
+                                || name_aliases(destination)
+                                    .iter()
+                                    .any(|alias| name_key(alias) == input))
+                    })
+                })
+            })
+    }
+
+    /// The direction as riders know it on the route, ie "Southbound to Ashmont/Braintree" on the
+    /// Red Line, falling back to inbound or outbound without the route's direction names
+    pub fn direction_label(&self, route_code: &str, direction_id: u8) -> String {
+        let details = self.routes.get(route_code);
+        let name = details
+            .and_then(|details| details.direction_names.get(direction_id as usize))
+            .filter(|name| !name.is_empty())
+            .map(|name| direction_name(name))
+            .unwrap_or_else(|| {
+                if direction_id == 1 {
+                    "Inbound".to_string()
+                } else {
+                    "Outbound".to_string()
+                }
+            });
+        match details
+            .and_then(|details| details.direction_destinations.get(direction_id as usize))
+            .filter(|destination| !destination.is_empty())
+        {
+            Some(destination) => format!("{} to {}", name, destination),
+            None => name,
+        }
+    }
+
+    /// API code of the parent station of the platform, or None when it is not a known platform
+    pub fn parent_station(&self, platform: &str) -> Option<&String> {
+        self.stations
+            .iter()
+            .find(|(_, details)| details.child_stops.iter().any(|child| child == platform))
+            .map(|(station_code, _)| station_code)
+    }
+
+    /// The platform with the stop number, along with the routes at its station, or None when it
+    /// is not a known platform.  Other stops, such as bus stops, are found with
+    /// `TransitProvider::stop`
+    pub fn platform(&self, stop_number: &str) -> Option<Stop> {
+        let parent_station = self.parent_station(stop_number)?;
+        Some(Stop {
+            name: self.station_name(parent_station),
+            parent_station: Some(parent_station.clone()),
+            routes: self.routes_at(std::slice::from_ref(parent_station)),
         })
-        .collect();
-    Ok(vehicles)
-}
-
-/// Retrieve commuter rail conversion for MBTA API from common understandable name to MTBA API code
-fn retrieve_commuter() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    println!("Starting Commuter");
-    // use the commuter rail schedule website to find the commuter rail codes which are located within the buttons
-    let commuter_url = "https://www.mbta.com/schedules/commuter-rail";
-    // parse the commuter rail schedule website
-    let commuter_info = parse_schedule_website(
-        commuter_url,
-        r#"a[class="c-grid-button c-grid-button--commuter-rail"]"#,
-        r#"span[class="c-grid-button__name"]"#,
-    )?;
-    // crate a hashmap out of the conversion information
-    let commuter_conversion: HashMap<String, String> = commuter_info
-        .iter()
-        .map(|commuter| (commuter[0].clone(), commuter[1].clone()))
-        .collect();
-    println!("Finished Commuter");
-    Ok(commuter_conversion)
-}
-
-/// Retrieve ferry conversion for MBTA API from common understandable name to MTBA API code
-fn retrieve_ferry() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    println!("Starting Ferry");
-    // use the ferry schedule website to find the ferry codes which are located within the buttons
-    let ferry_url = "https://www.mbta.com/schedules/ferry";
-    // parse the ferry schedule website
-    let ferry_info = parse_schedule_website(
-        ferry_url,
-        r#"a[class="c-grid-button c-grid-button--ferry"]"#,
-        r#"span[class="c-grid-button__name"]"#,
-    )?;
-    // crate a hashmap out of the conversion information
-    let ferry_conversion: HashMap<String, String> = ferry_info
-        .iter()
-        .map(|ferry| (ferry[0].clone(), ferry[1].clone()))
-        .collect();
-    println!("Finished Ferry");
-    Ok(ferry_conversion)
-}
-
-/// Retrieve subway conversion for MBTA API from common understandable name to MTBA API code.
-fn retrieve_subway() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    println!("Starting Subway");
-    // use the subway schedule website to get the conversion information from the buttons
-    let subway_url = "https://www.mbta.com/schedules/subway";
-    // buttons are setup slightly different than the commuter rail.  Each colored line starts with the &str below but finishes with the color, so each needs to be determined for a scraper selector
-    let button_selectors = partial_selector_match(subway_url, "c-grid-button c-grid-button--")?;
-    // parse all button selectors and find subway conversion information.  Place into a hashmap
-    let mut subway_info;
-    let mut subway_conversion = HashMap::new();
-    for button_selector in button_selectors.iter() {
-        subway_info = parse_schedule_website(
-            subway_url,
-            &format!("a[class=\"{}\"]", button_selector),
-            r#"span[class="c-grid-button__name"]"#,
-        )?;
-        subway_conversion.extend(
-            subway_info
-                .iter()
-                .map(|subway| (subway[0].clone(), subway[1].clone())),
-        );
     }
-    // green line buttons are condensed for each line.  This parses for these buttons
-    let green_lines_info = parse_schedule_website(
-        subway_url,
-        r#"a[class="c-grid-button__condensed"]"#,
-        r#"svg[role="img"]"#,
-    )?;
-    // add green lines to the subway hashmap
-    subway_conversion.extend(
-        green_lines_info
+
+    /// Name riders know the route by, ie "Needham Line", falling back to the command line name
+    /// for catalogs without the route details
+    pub fn route_name(&self, route_code: &str) -> String {
+        if let Some(details) = self
+            .routes
+            .get(route_code)
+            .filter(|details| !details.name.is_empty())
+        {
+            return details.name.clone();
+        }
+        self.vehicle_info
+            .values()
+            .flatten()
+            .find(|(_, code)| *code == route_code)
+            .map(|(line, _)| line.replace('_', " "))
+            .unwrap_or_else(|| route_code.to_string())
+    }
+
+    /// Name riders know the station by, ie "Forest Hills", falling back to the command line name
+    /// for catalogs without the station details
+    pub fn station_name(&self, station_code: &str) -> String {
+        if let Some(details) = self
+            .stations
+            .get(station_code)
+            .filter(|details| !details.name.is_empty())
+        {
+            return details.name.clone();
+        }
+        self.station_info
             .iter()
-            .map(|green| (green[1].clone(), green[1].clone())),
+            .find(|(_, station_codes)| station_codes.contains_key(station_code))
+            .map(|(station, _)| station.replace('_', " "))
+            .unwrap_or_else(|| station_code.to_string())
+    }
+}
+
+/// The direction name with "bound", ie "Southbound" for the Red Line's "South".  Names such as
+/// "Inbound" already have it
+fn direction_name(name: &str) -> String {
+    if name.to_lowercase().ends_with("bound") {
+        name.to_string()
+    } else {
+        format!("{}bound", name)
+    }
+}
+
+/// Converts a station or line name into the form used for command line input, ie "Forest Hills"
+/// becomes "Forest_Hills"
+pub fn input_name(name: &str) -> String {
+    name.replace(' ', "_").replace('\'', "")
+}
+
+/// Looks up a stop by its stop number with the MBTA API, along with the routes serving it, or
+/// None when there is no such stop.  Bus stops are not in the catalog, so they are looked up when
+/// counting down to them
+pub async fn find_stop(stop_number: &str) -> Result<Option<Stop>, Box<dyn std::error::Error>> {
+    let stops_json = get_api_json(&format!(
+        "https://api-v3.mbta.com/stops?filter[id]={}",
+        stop_number
+    ))
+    .await?;
+    if stops_json["data"].as_array().is_none_or(Vec::is_empty) {
+        return Ok(None);
+    }
+    let routes_json = get_api_json(&format!(
+        "https://api-v3.mbta.com/routes?filter[stop]={}",
+        stop_number
+    ))
+    .await?;
+    Ok(parse_stop(&stops_json, &routes_json))
+}
+
+/// The stop from the MBTA API stops and routes JSON, as in `find_stop`
+fn parse_stop(stops_json: &Value, routes_json: &Value) -> Option<Stop> {
+    let stop = &stops_json["data"][0];
+    stop["id"].as_str()?;
+    Some(Stop {
+        name: stop["attributes"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        parent_station: stop["relationships"]["parent_station"]["data"]["id"]
+            .as_str()
+            .map(|parent_station| parent_station.to_string()),
+        routes: routes_json["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|route| route["id"].as_str())
+            .map(|route| route.to_string())
+            .collect(),
+    })
+}
+
+/// Retrieves JSON from the MBTA V3 API, including the API key when there is one
+pub async fn get_api_json(address: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut request = reqwest::Client::new().get(address);
+    if let Ok(api_key) = env::var(MBTA_API_KEY_VAR) {
+        request = request.header("x-api-key", api_key);
+    }
+    Ok(request.send().await?.error_for_status()?.json().await?)
+}
+
+/// Retrieves JSON from the MBTA V3 API, retrying with a growing wait when the request fails
+async fn get_api_json_with_retries(
+    address: &str,
+    report: bool,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut attempt = 1;
+    loop {
+        // bound separately so the error is dropped before waiting
+        let result = get_api_json(address).await.map_err(|err| err.to_string());
+        let err = match result {
+            Ok(json) => return Ok(json),
+            Err(err) => err,
+        };
+        let delay = match retry_delay(attempt) {
+            Some(delay) => delay,
+            None => return Err(err.into()),
+        };
+        if report {
+            println!(
+                "Request to {} failed, retrying in {} seconds: {}",
+                address,
+                delay.as_secs(),
+                err
+            );
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Wait before retrying after the failed attempt, or None when there are no attempts left
+fn retry_delay(attempt: u32) -> Option<Duration> {
+    if attempt >= CATALOG_ATTEMPTS {
+        return None;
+    }
+    Some(CATALOG_RETRY_DELAY * 2u32.pow(attempt - 1))
+}
+
+/// Builds the catalog from the MBTA API routes, along with the stops of each route other than
+/// buses.  Bus stops are chosen by their stop number instead.  Also returns the routes whose
+/// stations could not be retrieved, along with why.  Progress is printed when `report` is set
+async fn retrieve_catalog(
+    report: bool,
+) -> Result<(Catalog, Vec<(String, String)>), Box<dyn std::error::Error>> {
+    let mut catalog = Catalog {
+        schema_version: CATALOG_SCHEMA_VERSION,
+        created: Some(Utc::now()),
+        source: MBTA_API_SOURCE.to_string(),
+        ..Catalog::default()
+    };
+    let mut failed_routes = Vec::new();
+    let routes_json = get_api_json_with_retries(
+        "https://api-v3.mbta.com/routes?filter[type]=0,1,2,3,4",
+        report,
+    )
+    .await?;
+    for route in routes_json["data"].as_array().into_iter().flatten() {
+        let route_code = match route["id"].as_str() {
+            Some(route_code) => route_code.to_string(),
+            None => continue,
+        };
+        let attributes = &route["attributes"];
+        let route_type = attributes["type"].as_u64().unwrap_or_default() as u8;
+        let long_name = attributes["long_name"].as_str().unwrap_or_default();
+        let short_name = attributes["short_name"].as_str().unwrap_or_default();
+        let name = line_name(&route_code, route_type, long_name, short_name);
+        catalog
+            .vehicle_info
+            .entry(vehicle_type(route_type).to_string())
+            .or_default()
+            .insert(input_name(&name), route_code.clone());
+        catalog.routes.insert(
+            route_code.clone(),
+            RouteDetails {
+                name: if long_name.is_empty() {
+                    short_name.to_string()
+                } else {
+                    long_name.to_string()
+                },
+                route_type,
+                direction_names: string_array(&attributes["direction_names"]),
+                direction_destinations: string_array(&attributes["direction_destinations"]),
+                aliases: route_aliases(&name, long_name, short_name),
+            },
+        );
+        if route_type != 3 {
+            if report {
+                println!("Retrieving stations for route: {}", route_code);
+            }
+            // one route failing leaves the rest of the catalog usable
+            let result = add_route_stations(&mut catalog, &route_code, report)
+                .await
+                .map_err(|err| err.to_string());
+            if let Err(err) = result {
+                failed_routes.push((route_code, err));
+            }
+        }
+    }
+    Ok((catalog, failed_routes))
+}
+
+/// Adds the stations the route stops at, along with their platforms, to the catalog
+async fn add_route_stations(
+    catalog: &mut Catalog,
+    route_code: &str,
+    report: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let address = format!(
+        "https://api-v3.mbta.com/stops?filter[route]={}&include=child_stops",
+        route_code
     );
-    println!("Finished Subway");
-    Ok(subway_conversion)
-}
-
-/// Use to find all matches within an HTML when only a part is known.  For example here, when all subway button selectors start with 'c-grid-button--' but end with a different word.  This will find all occurances
-fn partial_selector_match(
-    url: &str,
-    partial_match: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    // Get all website text
-    let website_text = reqwest::blocking::get(url)?.text()?;
-    // find any line that contains the partial match
-    let selected_lines = website_text
-        .lines()
-        .filter(|website_line| website_line.contains(partial_match))
-        // splie out the line and only take the text within the quotes with the match.  This is the exact match for the scraper selector
-        .map(|website_line| {
-            website_line
-                .split('"')
-                .find(|inner_line| inner_line.contains(partial_match))
-                .unwrap()
-                .to_string()
-        })
-        .collect();
-    Ok(selected_lines)
-}
-
-/// Parses the schedule website.  This website is used to pull all vehicle information
-fn parse_schedule_website(
-    url: &str,
-    button_select_str: &str,
-    inner_select_str: &str,
-) -> Result<Vec<[String; 2]>, Box<dyn std::error::Error>> {
-    // get website text and parse
-    let website_text = reqwest::blocking::get(url)?.text()?;
-    let document = Html::parse_document(&website_text);
-
-    // setup button selector and the inner HTML selector for the commonly known name for the train/subway
-    let button_selector = Selector::parse(button_select_str).unwrap();
-    let inner_selector = Selector::parse(inner_select_str).unwrap();
-
-    // iteratively go through each button and return the line and the href, which contains the code for the line used by the API
-    let button_selected = document.select(&button_selector);
-    let conversion_info: Vec<[String; 2]> = button_selected
-        .map(|button| {
-            [
-                button
-                    .select(&inner_selector)
-                    .last()
-                    .unwrap()
-                    .inner_html()
-                    .replace("\u{200b}", "")
-                    .replace("\n", "")
-                    .replace("Line", "")
-                    .trim()
-                    .replace(" ", "_")
-                    .replace("'", ""),
-                button
-                    .value()
-                    .attr("href")
-                    .unwrap()
-                    .replace("/schedules/", ""),
-            ]
+    let stops_json = get_api_json_with_retries(&address, report).await?;
+    // child stop id -> location type, to only keep the platforms and not the entrances
+    let location_types: HashMap<&str, u64> = stops_json["included"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|stop| {
+            Some((
+                stop["id"].as_str()?,
+                stop["attributes"]["location_type"].as_u64()?,
+            ))
         })
         .collect();
-    Ok(conversion_info)
+    for stop in stops_json["data"].as_array().into_iter().flatten() {
+        let station_code = match stop["id"].as_str() {
+            Some(station_code) => station_code.to_string(),
+            None => continue,
+        };
+        let attributes = &stop["attributes"];
+        let name = attributes["name"].as_str().unwrap_or_default().to_string();
+        let child_stops = stop["relationships"]["child_stops"]["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|child| child["id"].as_str())
+            .filter(|child| {
+                location_types
+                    .get(child)
+                    .is_some_and(|location| *location == 0)
+            })
+            .map(|child| child.to_string())
+            .collect();
+        let routes = catalog
+            .station_info
+            .entry(input_name(&name))
+            .or_default()
+            .entry(station_code.clone())
+            .or_default();
+        if !routes.iter().any(|route| route == route_code) {
+            routes.push(route_code.to_string());
+        }
+        catalog.stations.insert(
+            station_code,
+            StationDetails {
+                latitude: attributes["latitude"].as_f64(),
+                longitude: attributes["longitude"].as_f64(),
+                wheelchair_boarding: attributes["wheelchair_boarding"]
+                    .as_u64()
+                    .unwrap_or_default() as u8,
+                aliases: name_aliases(&name),
+                name,
+                child_stops,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Vehicle type used to group the routes for the command line, from the GTFS route type
+pub fn vehicle_type(route_type: u8) -> &'static str {
+    match route_type {
+        2 => "Commuter_Rail",
+        3 | 11 => "Bus",
+        4 => "Ferry",
+        // tram, subway, cable tram, aerial lift, funicular and monorail
+        _ => "Subway",
+    }
+}
+
+/// The line name used for command line input, ie Needham for the Needham Line.  Buses use their
+/// route number and the Green Line branches their route code, ie Green-B
+fn line_name(route_code: &str, route_type: u8, long_name: &str, short_name: &str) -> String {
+    if route_type == 3 || long_name.is_empty() {
+        short_name.to_string()
+    } else if route_code.starts_with("Green-") {
+        route_code.to_string()
+    } else {
+        long_name.replace(" Line", "").trim().to_string()
+    }
+}
+
+/// Other names for the route beyond its command line and display names, ie the short name "B"
+/// for the Green Line B branch, along with the names within a name such as
+/// "Providence/Stoughton"
+fn route_aliases(line: &str, long_name: &str, short_name: &str) -> Vec<String> {
+    let mut aliases = name_aliases(line);
+    if !short_name.is_empty() && short_name != line && short_name != long_name {
+        aliases.push(short_name.to_string());
+    }
+    aliases
+}
+
+/// The strings within a JSON array, with any others left empty
+fn string_array(json: &Value) -> Vec<String> {
+    json.as_array()
+        .into_iter()
+        .flatten()
+        .map(|value| value.as_str().unwrap_or_default().to_string())
+        .collect()
+}
+
+/// Writes the JSON to a temporary file then renames it over the file, so an interrupted write
+/// never leaves a half-written file behind
+fn write_json<T: Serialize>(file_loc: &Path, value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    let temp_file_loc = file_loc.with_extension("tmp");
+    let f = File::create(&temp_file_loc)?;
+    let mut bw = BufWriter::new(f);
+    serde_json::to_writer(&mut bw, value)?;
+    bw.into_inner()?.sync_all()?;
+    fs::rename(&temp_file_loc, file_loc)?;
+    Ok(())
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(
+    file_loc: &Path,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let g = File::open(file_loc)?;
+    let reader = BufReader::new(g);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_is_current_format() {
+        let snapshot = snapshot_catalog().unwrap();
+        assert_eq!(snapshot.schema_version, CATALOG_SCHEMA_VERSION);
+        assert!(snapshot.vehicle_info["Commuter_Rail"].contains_key("Needham"));
+        assert!(snapshot.station_info.contains_key("Park_Street"));
+    }
+
+    #[test]
+    fn stops_from_the_api() {
+        let routes_json = serde_json::json!({
+            "data": [{"id": "39", "type": "route"}, {"id": "Orange", "type": "route"}]
+        });
+        let bus_stop = parse_stop(
+            &serde_json::json!({
+                "data": [{
+                    "id": "1234",
+                    "attributes": {"name": "Huntington Ave @ Ruggles St"},
+                    "relationships": {"parent_station": {"data": null}}
+                }]
+            }),
+            &routes_json,
+        )
+        .unwrap();
+        assert_eq!(bus_stop.name, "Huntington Ave @ Ruggles St");
+        assert_eq!(bus_stop.parent_station, None);
+        assert_eq!(bus_stop.routes, ["39", "Orange"]);
+        let platform = parse_stop(
+            &serde_json::json!({
+                "data": [{
+                    "id": "70061",
+                    "attributes": {"name": "Alewife"},
+                    "relationships": {"parent_station": {"data": {"id": "place-alfcl"}}}
+                }]
+            }),
+            &routes_json,
+        )
+        .unwrap();
+        assert_eq!(platform.parent_station.as_deref(), Some("place-alfcl"));
+        assert!(parse_stop(&serde_json::json!({"data": []}), &routes_json).is_none());
+    }
+
+    #[test]
+    fn names_in_any_form() {
+        let snapshot = snapshot_catalog().unwrap();
+        for input in &["Park_Street", "park street", "PARK-STREET", "place-pktrm"] {
+            assert_eq!(snapshot.find_stations(input), ["Park_Street"]);
+        }
+        assert_eq!(
+            snapshot.find_route("Commuter_Rail", "stoughton").unwrap(),
+            "CR-Providence"
+        );
+        assert_eq!(
+            snapshot.find_route("Subway", "green line b").unwrap(),
+            "Green-B"
+        );
+        assert!(snapshot.find_route("Subway", "Needham").is_none());
+        assert_eq!(snapshot.route_name("CR-Needham"), "Needham Line");
+        assert_eq!(snapshot.station_name("place-forhl"), "Forest Hills");
+    }
+
+    #[test]
+    fn stations_with_several_stops() {
+        let mut catalog = Catalog::default();
+        let mut hub = HashMap::new();
+        hub.insert("place-hub-orange".to_string(), vec!["Orange".to_string()]);
+        hub.insert("place-hub-green".to_string(), vec!["Green-E".to_string()]);
+        hub.insert("place-hub-bus".to_string(), vec!["39".to_string()]);
+        catalog.station_info.insert("Hub".to_string(), hub);
+        catalog.stations.insert(
+            "place-hub-green".to_string(),
+            StationDetails {
+                name: "Hub".to_string(),
+                child_stops: vec!["70001".to_string()],
+                ..StationDetails::default()
+            },
+        );
+        let routes = vec!["Orange".to_string(), "Green-E".to_string()];
+        // the same stops every time, leaving out the ones the routes do not serve
+        assert_eq!(
+            catalog.station_stops("Hub", &routes),
+            ["place-hub-green", "place-hub-orange"]
+        );
+        assert_eq!(
+            catalog.station_stops("Hub", &["Red".to_string()]),
+            ["place-hub-bus", "place-hub-green", "place-hub-orange"]
+        );
+        assert_eq!(
+            catalog.routes_at(&["place-hub-green".to_string()]),
+            ["Green-E"]
+        );
+        // a platform finds its station
+        assert_eq!(catalog.find_stations("70001"), ["Hub"]);
+        // every station sharing an alias is found
+        catalog
+            .station_info
+            .insert("Hub_Square".to_string(), HashMap::new());
+        catalog.stations.get_mut("place-hub-green").unwrap().aliases = vec!["Hub Sq".to_string()];
+        catalog.stations.insert(
+            "place-hub-square".to_string(),
+            StationDetails {
+                name: "Hub Square".to_string(),
+                aliases: vec!["Hub Sq".to_string()],
+                ..StationDetails::default()
+            },
+        );
+        catalog
+            .station_info
+            .get_mut("Hub_Square")
+            .unwrap()
+            .insert("place-hub-square".to_string(), Vec::new());
+        assert_eq!(catalog.find_stations("hub sq"), ["Hub", "Hub_Square"]);
+        assert_eq!(catalog.parent_station("70001").unwrap(), "place-hub-green");
+        let platform = catalog.platform("70001").unwrap();
+        assert_eq!(platform.name, "Hub");
+        assert_eq!(platform.parent_station.as_deref(), Some("place-hub-green"));
+        assert_eq!(platform.routes, ["Green-E"]);
+        assert!(catalog.platform("1234").is_none());
+    }
+
+    #[test]
+    fn direction_names() {
+        let mut catalog = Catalog::default();
+        catalog.routes.insert(
+            "Red".to_string(),
+            RouteDetails {
+                name: "Red Line".to_string(),
+                route_type: 1,
+                direction_names: vec!["South".to_string(), "North".to_string()],
+                direction_destinations: vec![
+                    "Ashmont/Braintree".to_string(),
+                    "Alewife".to_string(),
+                ],
+                aliases: Vec::new(),
+            },
+        );
+        let red = vec!["Red".to_string()];
+        for (input, direction_id) in &[
+            ("southbound", 0),
+            ("North", 1),
+            ("Ashmont", 0),
+            ("alewife", 1),
+            ("inbound", 1),
+            ("0", 0),
+        ] {
+            assert_eq!(catalog.find_direction(&red, input), Some(*direction_id));
+        }
+        assert_eq!(catalog.find_direction(&red, "Westbound"), None);
+        assert_eq!(
+            catalog.direction_label("Red", 0),
+            "Southbound to Ashmont/Braintree"
+        );
+        // routes without direction names
+        assert_eq!(catalog.direction_label("CR-Needham", 1), "Inbound");
+    }
+
+    #[test]
+    fn stale_catalogs() {
+        let mut catalog = Catalog::default();
+        // a catalog of unknown age is refreshed
+        assert!(catalog.is_stale());
+        catalog.created = Some(Utc::now() - chrono::Duration::days(2));
+        assert!(!catalog.is_stale());
+        catalog.created = Some(Utc::now() - chrono::Duration::days(CATALOG_MAX_AGE_DAYS + 1));
+        assert!(catalog.is_stale());
+    }
+
+    #[test]
+    fn retries_wait_longer_each_time() {
+        assert_eq!(retry_delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(retry_delay(2), Some(Duration::from_secs(4)));
+        assert_eq!(retry_delay(3), Some(Duration::from_secs(8)));
+        assert_eq!(retry_delay(CATALOG_ATTEMPTS), None);
+    }
+
+    #[test]
+    fn failed_routes_keep_previous_stations() {
+        let mut previous = Catalog::default();
+        let mut needham = HashMap::new();
+        needham.insert(
+            "place-NHRML-0127".to_string(),
+            vec!["CR-Needham".to_string()],
+        );
+        previous
+            .station_info
+            .insert("Needham_Heights".to_string(), needham);
+        let mut forest_hills = HashMap::new();
+        forest_hills.insert(
+            "place-forhl".to_string(),
+            vec!["CR-Needham".to_string(), "Orange".to_string()],
+        );
+        previous
+            .station_info
+            .insert("Forest_Hills".to_string(), forest_hills);
+        previous.stations.insert(
+            "place-NHRML-0127".to_string(),
+            StationDetails {
+                name: "Needham Heights".to_string(),
+                ..StationDetails::default()
+            },
+        );
+        // the refresh only retrieved the Orange Line's stations
+        let mut catalog = Catalog::default();
+        let mut forest_hills = HashMap::new();
+        forest_hills.insert("place-forhl".to_string(), vec!["Orange".to_string()]);
+        catalog
+            .station_info
+            .insert("Forest_Hills".to_string(), forest_hills);
+        keep_previous_stations(
+            &mut catalog,
+            &previous,
+            &[("CR-Needham".to_string(), "timed out".to_string())],
+        );
+        assert_eq!(
+            catalog.station_info["Needham_Heights"]["place-NHRML-0127"],
+            ["CR-Needham"]
+        );
+        assert_eq!(
+            catalog.station_info["Forest_Hills"]["place-forhl"],
+            ["Orange", "CR-Needham"]
+        );
+        assert_eq!(catalog.station_name("place-NHRML-0127"), "Needham Heights");
+    }
+
+    /// Empty directory for the files of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let test_dir =
+            env::temp_dir().join(format!("mbta_countdown_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn legacy_files_are_migrated() {
+        let legacy_dir = test_dir("legacy");
+        let mut needham = HashMap::new();
+        needham.insert("Needham".to_string(), "CR-Needham".to_string());
+        let mut vehicle_info: VehicleInfo = HashMap::new();
+        vehicle_info.insert("Commuter_Rail".to_string(), needham);
+        let mut station_codes = HashMap::new();
+        station_codes.insert(
+            "place-NHRML-0127".to_string(),
+            vec!["CR-Needham".to_string()],
+        );
+        let mut station_info: StationInfo = HashMap::new();
+        station_info.insert("Needham_Heights".to_string(), station_codes);
+        write_json(&legacy_dir.join(LEGACY_VEHICLE_FILE), &vehicle_info).unwrap();
+        write_json(&legacy_dir.join(LEGACY_STATION_FILE), &station_info).unwrap();
+
+        let catalog_file = legacy_dir.join("cache").join("catalog.json");
+        let migrated = migrate_legacy_catalog(&catalog_file, &legacy_dir)
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.schema_version, CATALOG_SCHEMA_VERSION);
+        assert_eq!(migrated.vehicle_info, vehicle_info);
+        assert_eq!(migrated.station_info, station_info);
+        // the legacy files are replaced by the catalog file
+        assert!(!legacy_dir.join(LEGACY_VEHICLE_FILE).exists());
+        assert!(!legacy_dir.join(LEGACY_STATION_FILE).exists());
+        let saved = read_catalog(&catalog_file, false).unwrap().unwrap();
+        assert_eq!(saved.vehicle_info, migrated.vehicle_info);
+        assert_eq!(saved.station_info, migrated.station_info);
+        assert!(migrate_legacy_catalog(&catalog_file, &legacy_dir)
+            .unwrap()
+            .is_none());
+        fs::remove_dir_all(&legacy_dir).unwrap();
+    }
+
+    #[test]
+    fn catalog_format_versions() {
+        let catalog_dir = test_dir("versions");
+        let catalog_file = catalog_dir.join("catalog.json");
+        // older formats are refreshed
+        write_json(&catalog_file, &serde_json::json!({"schema_version": 0})).unwrap();
+        assert!(read_catalog(&catalog_file, false).unwrap().is_none());
+        write_json(&catalog_file, &serde_json::json!({"Subway": {}})).unwrap();
+        assert!(read_catalog(&catalog_file, false).unwrap().is_none());
+        // newer formats are not overwritten
+        write_json(
+            &catalog_file,
+            &serde_json::json!({"schema_version": CATALOG_SCHEMA_VERSION + 1}),
+        )
+        .unwrap();
+        assert!(read_catalog(&catalog_file, false).is_err());
+        fs::remove_dir_all(&catalog_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_without_saved_catalog() {
+        let catalog_dir = test_dir("snapshot");
+        let catalog = all_mbta_info(false, &catalog_dir.join("catalog.json"))
+            .await
+            .unwrap();
+        let snapshot = snapshot_catalog().unwrap();
+        assert_eq!(catalog.source, snapshot.source);
+        assert_eq!(catalog.vehicle_info, snapshot.vehicle_info);
+        assert_eq!(catalog.station_info, snapshot.station_info);
+        // nothing is saved until the catalog is refreshed
+        assert!(!catalog_dir.join("catalog.json").exists());
+        fs::remove_dir_all(&catalog_dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "the snapshot is hand made until it is regenerated with --write_snapshot"]
+    fn snapshot_is_complete() {
+        let snapshot = snapshot_catalog().unwrap();
+        assert!(snapshot.created.is_some());
+        assert!(!snapshot.vehicle_info["Bus"].is_empty());
+        assert!(snapshot
+            .stations
+            .values()
+            .all(|station| station.latitude.is_some() && station.longitude.is_some()));
+        let park_street = &snapshot.stations["place-pktrm"];
+        assert!(!park_street.child_stops.is_empty());
+        // platforms are checked against their station's routes without looking them up
+        let alewife = snapshot.platform("70061").unwrap();
+        assert_eq!(alewife.parent_station.as_deref(), Some("place-alfcl"));
+        assert!(alewife.routes.contains(&"Red".to_string()));
+        assert!(snapshot.routes["Green-B"]
+            .aliases
+            .contains(&"B".to_string()));
+        assert!(snapshot.routes["CR-Needham"]
+            .direction_destinations
+            .iter()
+            .all(|destination| !destination.is_empty()));
+    }
 }
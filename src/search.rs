@@ -0,0 +1,386 @@
+use crate::mbta_info::{name_key, Catalog};
+
+/// Most matches returned by a search
+pub const MAX_MATCHES: usize = 10;
+
+/// A station or route matching a search
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    /// Name riders know the station or route by, ie "South Station"
+    pub name: String,
+    /// "station", or the vehicle type of a route, ie "commuter rail"
+    pub kind: String,
+    /// Command line flag choosing the station or route, ie "-s South_Station"
+    pub flag: String,
+    /// Names and flags of the routes serving a station, ie ("Needham Line", "-c Needham")
+    pub routes: Vec<(String, String)>,
+    /// How closely the search matched, higher is closer
+    pub score: u32,
+}
+
+/// Finds the stations and routes best matching the search, closest first.  Typos and
+/// abbreviations are tolerated, so "jfk" finds JFK/UMass and "sstation" finds South Station
+///
+/// # Arguments
+///
+///  * `catalog` - the route and station information to search
+///  * `search` - what was typed, in any case, spacing or punctuation
+pub fn search(catalog: &Catalog, search: &str) -> Vec<SearchMatch> {
+    let search = name_key(search);
+    if search.is_empty() {
+        return Vec::new();
+    }
+    let mut matches = Vec::new();
+
+    for (station, station_codes) in &catalog.station_info {
+        let mut names = vec![station.clone()];
+        for station_code in station_codes.keys() {
+            names.push(station_code.clone());
+            if let Some(details) = catalog.stations.get(station_code) {
+                names.push(details.name.clone());
+                names.extend(details.aliases.iter().cloned());
+            }
+        }
+        if let Some(score) = best_score(&search, &names) {
+            let mut route_codes = station_codes.values().flatten().collect::<Vec<&String>>();
+            route_codes.sort_unstable();
+            route_codes.dedup();
+            let routes = route_codes
+                .into_iter()
+                .filter_map(|route_code| {
+                    Some((
+                        catalog.route_name(route_code),
+                        route_flag(catalog, route_code)?,
+                    ))
+                })
+                .collect();
+            matches.push(SearchMatch {
+                name: station_codes
+                    .keys()
+                    .next()
+                    .map(|station_code| catalog.station_name(station_code))
+                    .unwrap_or_else(|| station.replace('_', " ")),
+                kind: "station".to_string(),
+                flag: format!("-s {}", station),
+                routes,
+                score,
+            });
+        }
+    }
+
+    for (vehicle_type, lines) in &catalog.vehicle_info {
+        for (line, route_code) in lines {
+            let mut names = vec![line.clone(), route_code.clone()];
+            if let Some(details) = catalog.routes.get(route_code) {
+                names.push(details.name.clone());
+                names.extend(details.aliases.iter().cloned());
+            }
+            if let Some(score) = best_score(&search, &names) {
+                matches.push(SearchMatch {
+                    name: catalog.route_name(route_code),
+                    kind: vehicle_type.replace('_', " ").to_lowercase(),
+                    flag: format!("{} {}", vehicle_flag(vehicle_type), line),
+                    routes: Vec::new(),
+                    score,
+                });
+            }
+        }
+    }
+
+    // closest first, then the shortest names as the search covers more of them
+    matches.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.name.len().cmp(&b.name.len()))
+            .then(a.name.cmp(&b.name))
+    });
+    matches.truncate(MAX_MATCHES);
+    matches
+}
+
+/// A station near a location
+#[derive(Clone, Debug, PartialEq)]
+pub struct NearbyStation {
+    /// Name riders know the station by, ie "South Station"
+    pub name: String,
+    /// Command line flag choosing the station, ie "-s South_Station"
+    pub flag: String,
+    /// Command line name of the station, ie "South_Station"
+    pub station: String,
+    pub distance_km: f64,
+    /// Routes serving the station
+    pub routes: Vec<NearbyRoute>,
+}
+
+/// A route serving a nearby station, along with its directions
+#[derive(Clone, Debug, PartialEq)]
+pub struct NearbyRoute {
+    /// Name riders know the route by, ie "Red Line"
+    pub name: String,
+    /// Command line flag choosing the route, ie "-l Red"
+    pub flag: String,
+    /// Vehicle type and command line name of the route, ie ("Subway", "Red")
+    pub line: (String, String),
+    /// Direction flag and where the direction goes, ie ("-d outbound", "South to Ashmont/Braintree")
+    pub directions: Vec<(String, String)>,
+}
+
+/// Finds the stations within the radius of the location, closest first.  Only stations with
+/// their location in the catalog are found
+///
+/// # Arguments
+///
+///  * `catalog` - the route and station information to search
+///  * `latitude` - latitude of the location in degrees
+///  * `longitude` - longitude of the location in degrees
+///  * `radius_km` - furthest a station can be in kilometres
+pub fn nearby(
+    catalog: &Catalog,
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+) -> Vec<NearbyStation> {
+    let mut stations = Vec::new();
+    for (station, station_codes) in &catalog.station_info {
+        // the closest of the station's codes with a location
+        let closest = station_codes
+            .keys()
+            .filter_map(|station_code| {
+                let details = catalog.stations.get(station_code)?;
+                let distance_km =
+                    distance_km(latitude, longitude, details.latitude?, details.longitude?);
+                Some((station_code, distance_km))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let (station_code, distance_km) = match closest {
+            Some(closest) if closest.1 <= radius_km => closest,
+            _ => continue,
+        };
+        let mut route_codes = station_codes.values().flatten().collect::<Vec<&String>>();
+        route_codes.sort_unstable();
+        route_codes.dedup();
+        let routes = route_codes
+            .into_iter()
+            .filter_map(|route_code| {
+                Some(NearbyRoute {
+                    name: catalog.route_name(route_code),
+                    flag: route_flag(catalog, route_code)?,
+                    line: route_line(catalog, route_code)?,
+                    directions: directions(catalog, route_code),
+                })
+            })
+            .collect();
+        stations.push(NearbyStation {
+            name: catalog.station_name(station_code),
+            flag: format!("-s {}", station),
+            station: station.clone(),
+            distance_km,
+            routes,
+        });
+    }
+    stations.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    stations.truncate(MAX_MATCHES);
+    stations
+}
+
+/// The direction flags of the route, with how riders know each direction, ie
+/// ("-d outbound", "Southbound to Ashmont/Braintree")
+fn directions(catalog: &Catalog, route_code: &str) -> Vec<(String, String)> {
+    // the API direction ids, 0 for outbound and 1 for inbound
+    ["outbound", "inbound"]
+        .iter()
+        .enumerate()
+        .map(|(direction_id, direction)| {
+            (
+                format!("-d {}", direction),
+                catalog.direction_label(route_code, direction_id as u8),
+            )
+        })
+        .collect()
+}
+
+/// Great circle distance in kilometres between two locations given in degrees
+pub fn distance_km(latitude1: f64, longitude1: f64, latitude2: f64, longitude2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (lat1, lat2) = (latitude1.to_radians(), latitude2.to_radians());
+    let half_lat = (lat2 - lat1) / 2.0;
+    let half_lon = (longitude2 - longitude1).to_radians() / 2.0;
+    let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_lon.sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Command line flag for the routes of the vehicle type
+fn vehicle_flag(vehicle_type: &str) -> &'static str {
+    match vehicle_type {
+        "Commuter_Rail" => "-c",
+        "Ferry" => "-f",
+        "Bus" => "--bus",
+        _ => "-l",
+    }
+}
+
+/// Command line flag choosing the route, ie "-c Needham"
+fn route_flag(catalog: &Catalog, route_code: &str) -> Option<String> {
+    let (vehicle_type, line) = route_line(catalog, route_code)?;
+    Some(format!("{} {}", vehicle_flag(&vehicle_type), line))
+}
+
+/// Vehicle type and command line name of the route, ie ("Commuter_Rail", "Needham")
+fn route_line(catalog: &Catalog, route_code: &str) -> Option<(String, String)> {
+    catalog
+        .vehicle_info
+        .iter()
+        .find_map(|(vehicle_type, lines)| {
+            let (line, _) = lines.iter().find(|(_, code)| *code == route_code)?;
+            Some((vehicle_type.clone(), line.clone()))
+        })
+}
+
+/// The best score of the search against any of the names
+fn best_score(search: &str, names: &[String]) -> Option<u32> {
+    names
+        .iter()
+        .filter_map(|name| score(search, &name_key(name)))
+        .max()
+}
+
+/// How closely the search matches the name, both as from `name_key`, or None when it does not
+fn score(search: &str, name: &str) -> Option<u32> {
+    if name.is_empty() {
+        return None;
+    }
+    if name == search {
+        return Some(100);
+    }
+    if name.starts_with(search) {
+        return Some(90);
+    }
+    if name.contains(search) {
+        return Some(80);
+    }
+    // abbreviations keep the letters of the name in order, ie "sstation" for South Station.
+    // Short searches match too much this way
+    let search_len = search.chars().count();
+    if search_len >= 3 && is_subsequence(search, name) {
+        return Some(60);
+    }
+    // typos, compared to the whole name or to its start for a partly typed name
+    if search_len >= 4 {
+        let allowed = (search_len / 4) as u32;
+        let start = name.chars().take(search_len).collect::<String>();
+        let distance = edit_distance(search, name).min(edit_distance(search, &start));
+        if distance <= allowed {
+            return Some(50 - distance * 10);
+        }
+    }
+    None
+}
+
+/// Whether the letters of the search appear in the name in the same order
+fn is_subsequence(search: &str, name: &str) -> bool {
+    let mut name_chars = name.chars();
+    search.chars().all(|c| name_chars.any(|n| n == c))
+}
+
+/// Number of letters added, removed, changed or swapped with their neighbour to turn a into b
+fn edit_distance(a: &str, b: &str) -> u32 {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    // distances[i][j] is the distance between the first i letters of a and first j letters of b
+    let mut distances = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i as u32;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j as u32;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbta_info::snapshot_catalog;
+
+    fn first_match(search_text: &str) -> SearchMatch {
+        search(&snapshot_catalog().unwrap(), search_text)
+            .into_iter()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn abbreviations() {
+        assert_eq!(first_match("jfk").flag, "-s JFK/UMass");
+        assert_eq!(first_match("sstation").flag, "-s South_Station");
+    }
+
+    #[test]
+    fn typos() {
+        assert_eq!(first_match("alewif").flag, "-s Alewife");
+        assert_eq!(first_match("forset hills").flag, "-s Forest_Hills");
+        assert_eq!(first_match("neeham").flag, "-c Needham");
+    }
+
+    #[test]
+    fn routes_at_station() {
+        let park_street = first_match("Park Street");
+        assert_eq!(park_street.name, "Park Street");
+        assert!(park_street
+            .routes
+            .contains(&("Red Line".to_string(), "-l Red".to_string())));
+    }
+
+    #[test]
+    fn nearest_stations() {
+        let mut catalog = snapshot_catalog().unwrap();
+        for (station_code, latitude, longitude) in &[
+            ("place-pktrm", 42.356395, -71.062424),
+            ("place-sstat", 42.352271, -71.055242),
+            ("place-alfcl", 42.39674, -71.121815),
+        ] {
+            let details = catalog.stations.get_mut(*station_code).unwrap();
+            details.latitude = Some(*latitude);
+            details.longitude = Some(*longitude);
+        }
+        // from Downtown Crossing, with Alewife too far away
+        let stations = nearby(&catalog, 42.355518, -71.060225, 1.0);
+        let flags = stations
+            .iter()
+            .map(|station| station.flag.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(flags, ["-s Park_Street", "-s South_Station"]);
+        let red_line = stations[0]
+            .routes
+            .iter()
+            .find(|route| route.flag == "-l Red")
+            .unwrap();
+        assert_eq!(red_line.directions[1].0, "-d inbound");
+    }
+
+    #[test]
+    fn distances() {
+        // Park Street to South Station
+        let distance = distance_km(42.356395, -71.062424, 42.352271, -71.055242);
+        assert!((distance - 0.75).abs() < 0.05);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("alewife", "alewife"), 0);
+        assert_eq!(edit_distance("forsethills", "foresthills"), 1);
+        assert_eq!(edit_distance("needham", "neeham"), 1);
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// Hour of the day that the MBTA service day starts.  Trips running after midnight but before
+/// this hour belong to the previous day's service
+pub const SERVICE_DAY_START_HOUR: i64 = 3;
+
+/// Finds the MBTA service date that a time belongs to.  Times from midnight up to 3 am belong to
+/// the previous day's service
+pub fn service_date<Tz: TimeZone>(time: &DateTime<Tz>) -> NaiveDate {
+    (time.naive_local() - Duration::hours(SERVICE_DAY_START_HOUR)).date()
+}
+
+/// The time that schedule times of a service date are measured from, which is noon minus 12
+/// hours.  This is midnight except on days that daylight saving time begins or ends
+pub fn service_day_reference<Tz: TimeZone>(date: NaiveDate, timezone: &Tz) -> DateTime<Tz> {
+    // noon is never skipped or repeated by daylight saving time
+    let noon = timezone
+        .from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap())
+        .unwrap();
+    noon - Duration::hours(12)
+}
+
+/// Creates the `HH:MM` value for the API `filter[min_time]` relative to the service date.  Times
+/// after midnight are past 24:00, ie 1:30 am is 25:30 of the previous service date
+pub fn min_time_filter<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    let reference = service_day_reference(service_date(time), &time.timezone());
+    let minutes = time.clone().signed_duration_since(reference).num_minutes();
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Creates the `YYYY-MM-DD` value for the API `filter[date]`
+pub fn date_filter<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    service_date(time).format("%Y-%m-%d").to_string()
+}
+
+/// Checks whether the second time belongs to a later service date than the first
+pub fn later_service_date<Tz: TimeZone>(first: &DateTime<Tz>, second: &DateTime<Tz>) -> bool {
+    service_date(second) > service_date(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    fn time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 7, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn service_date_rolls_over_at_3am() {
+        assert_eq!(
+            service_date(&time(9, 23, 59)),
+            NaiveDate::from_ymd_opt(2021, 7, 9).unwrap()
+        );
+        assert_eq!(
+            service_date(&time(10, 0, 0)),
+            NaiveDate::from_ymd_opt(2021, 7, 9).unwrap()
+        );
+        assert_eq!(
+            service_date(&time(10, 2, 59)),
+            NaiveDate::from_ymd_opt(2021, 7, 9).unwrap()
+        );
+        assert_eq!(
+            service_date(&time(10, 3, 0)),
+            NaiveDate::from_ymd_opt(2021, 7, 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn service_date_across_month_end() {
+        let time = Utc.with_ymd_and_hms(2021, 8, 1, 1, 15, 0).unwrap();
+        assert_eq!(
+            service_date(&time),
+            NaiveDate::from_ymd_opt(2021, 7, 31).unwrap()
+        );
+        assert_eq!(date_filter(&time), "2021-07-31");
+    }
+
+    #[test]
+    fn min_time_past_midnight() {
+        assert_eq!(min_time_filter(&time(9, 23, 59)), "23:59");
+        assert_eq!(min_time_filter(&time(10, 0, 0)), "24:00");
+        assert_eq!(min_time_filter(&time(10, 1, 30)), "25:30");
+        assert_eq!(min_time_filter(&time(10, 2, 59)), "26:59");
+        assert_eq!(min_time_filter(&time(10, 3, 0)), "03:00");
+        assert_eq!(min_time_filter(&time(10, 6, 5)), "06:05");
+    }
+
+    #[test]
+    fn min_time_across_dst() {
+        // spring forward, 2 am EST becomes 3 am EDT.  The service date of 3/13 runs through the
+        // skipped hour
+        let time = New_York.with_ymd_and_hms(2021, 3, 14, 1, 30, 0).unwrap();
+        assert_eq!(date_filter(&time), "2021-03-13");
+        assert_eq!(min_time_filter(&time), "25:30");
+        let time = New_York.with_ymd_and_hms(2021, 3, 14, 3, 0, 0).unwrap();
+        assert_eq!(date_filter(&time), "2021-03-14");
+        assert_eq!(min_time_filter(&time), "03:00");
+        // measuring from noon minus 12 hours keeps times after the change matching the clock
+        let time = New_York.with_ymd_and_hms(2021, 3, 14, 4, 0, 0).unwrap();
+        assert_eq!(min_time_filter(&time), "04:00");
+        let time = New_York.with_ymd_and_hms(2021, 3, 14, 12, 0, 0).unwrap();
+        assert_eq!(min_time_filter(&time), "12:00");
+
+        // fall back, 1:30 am occurs twice and both belong to the service date of 11/6
+        let first = New_York
+            .with_ymd_and_hms(2021, 11, 7, 1, 30, 0)
+            .earliest()
+            .unwrap();
+        let second = New_York
+            .with_ymd_and_hms(2021, 11, 7, 1, 30, 0)
+            .latest()
+            .unwrap();
+        assert_eq!(date_filter(&first), "2021-11-06");
+        assert_eq!(date_filter(&second), "2021-11-06");
+        assert_eq!(min_time_filter(&first), "25:30");
+        assert_eq!(min_time_filter(&second), "26:30");
+        let time = New_York.with_ymd_and_hms(2021, 11, 7, 3, 0, 0).unwrap();
+        assert_eq!(date_filter(&time), "2021-11-07");
+        assert_eq!(min_time_filter(&time), "03:00");
+    }
+
+    #[test]
+    fn late_train_is_same_service_date() {
+        // a last train at 00:45 belongs to the same service day as 23:00 the night before
+        assert!(!later_service_date(&time(9, 23, 0), &time(10, 0, 45)));
+        assert!(!later_service_date(&time(10, 0, 45), &time(10, 2, 59)));
+        assert!(later_service_date(&time(10, 0, 45), &time(10, 3, 0)));
+        assert!(later_service_date(&time(9, 22, 0), &time(10, 3, 0)));
+    }
+}
@@ -3,29 +3,59 @@ extern crate embedded_graphics;
 extern crate rppal; // Crate for SPI, I2C, and GPIO on raspberry pi
 extern crate ssd1306; // Crate for current I2C oled display
 
-use chrono::{DateTime, Local};
+use crate::train_time::{catchable, Occupancy, TimeType, TrainTime};
+use crate::transit_provider::VehiclePosition;
 use embedded_graphics::{
-    fonts::{Font12x16, Text},
+    fonts::{Font12x16, Font6x8, Text},
     pixelcolor::BinaryColor,
     prelude::*,
-    style::TextStyleBuilder,
+    primitives::Rectangle,
+    style::{PrimitiveStyle, TextStyleBuilder},
 };
 use rppal::i2c::I2c;
 use ssd1306::{prelude::*, Builder, I2CDIBuilder};
+use std::{collections::HashMap, time::Instant};
+
+/// Seconds a newly assigned track stays highlighted
+const TRACK_HIGHLIGHT_SECS: u64 = 120;
 
 /// Structure that contains screen information
 pub struct ScreenDisplay {
     display: GraphicsMode<I2CInterface<I2c>>,
     // the closest train time
-    train1: Option<DateTime<Local>>,
+    train1: Option<TrainTime>,
     // the second closest train time
-    train2: Option<DateTime<Local>>,
+    train2: Option<TrainTime>,
+    // whether to label each time with its route, for when several routes are merged
+    show_route: bool,
+    // trip id -> track last seen, to find when a track is newly assigned
+    tracks: HashMap<String, Option<String>>,
+    // trip id -> when its track was assigned, while the track is highlighted
+    track_assigned: HashMap<String, Instant>,
+    // whether the track of train1 and train2 is highlighted on the screen
+    highlighted: [bool; 2],
+    // where the vehicle for train1 is
+    position: Option<VehiclePosition>,
+    // name of the destination the arrival times are for
+    destination_name: Option<String>,
+    // direction of the trains, ie "Southbound to Ashmont/Braintree"
+    direction_label: String,
+    // minutes to walk to the station, to show when to leave
+    walk_min: i64,
+    // minutes until leaving for train1 shown on the screen
+    leave_min: Option<i64>,
 }
 
 // functions to initialize and change screen display
 impl ScreenDisplay {
     /// Initializes a new screen display with empty train times
-    pub fn new(address: u16) -> Result<ScreenDisplay, Box<dyn std::error::Error>> {
+    pub fn new(
+        address: u16,
+        show_route: bool,
+        destination_name: Option<String>,
+        direction_label: String,
+        walk_min: i64,
+    ) -> Result<ScreenDisplay, Box<dyn std::error::Error>> {
         // bus4 I2c connection information
         let mut i2c = I2c::new()?;
         i2c.set_slave_address(address)?;
@@ -39,32 +69,74 @@ impl ScreenDisplay {
             display: disp,
             train1: None,
             train2: None,
+            show_route,
+            tracks: HashMap::new(),
+            track_assigned: HashMap::new(),
+            highlighted: [false; 2],
+            position: None,
+            destination_name,
+            direction_label,
+            walk_min,
+            leave_min: None,
         })
     }
 
-    /// Displays train1 and train2 on the screen display
+    /// Displays the next two trains that can be caught as train1 and train2 on the screen
+    /// display, along with when to leave for train1 and where its vehicle is when known
     pub fn display_trains(
         &mut self,
-        train_times: &[DateTime<Local>],
+        train_times: &[TrainTime],
+        position: Option<&VehiclePosition>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now();
+        // trains leaving before the walk to the station are skipped
+        let train_times = catchable(train_times, self.walk_min, now);
+        if train_times.is_empty() {
+            if self.train1.is_some() {
+                self.clear_display(true)?;
+            }
+            return Ok(());
+        }
         // create a variable to test whether or not the screen needs to be updated
         let mut update_screen = false;
+        let leave_min = train_times[0]
+            .leave_time(self.walk_min)
+            .signed_duration_since(now)
+            .num_minutes();
+        if self.leave_min != Some(leave_min) {
+            self.leave_min = Some(leave_min);
+            update_screen = true;
+        }
+        if self.position.as_ref() != position {
+            self.position = position.cloned();
+            update_screen = true;
+        }
         // if train1 is different than nearest train, replace with nearest train and update later
-        if Some(train_times[0]) != self.train1 {
-            self.train1 = Some(train_times[0]);
+        if self.train1.as_ref() != Some(&train_times[0]) {
+            self.train1 = Some(train_times[0].clone());
             update_screen = true;
         }
         // if there is more than one train time, proceed with train2
         if train_times.len() > 1 {
             // if train 2 is different from second train, replace and update
-            if Some(train_times[1]) != self.train2 {
-                self.train2 = Some(train_times[1]);
+            if self.train2.as_ref() != Some(&train_times[1]) {
+                self.train2 = Some(train_times[1].clone());
                 update_screen = true;
             }
         } else {
             // if there is not more than one train time, set train2 as none
             self.train2 = None
         }
+        // update when a track highlight starts or ends
+        self.update_tracks(&train_times);
+        let highlighted = [
+            self.track_highlighted(&self.train1),
+            self.track_highlighted(&self.train2),
+        ];
+        if highlighted != self.highlighted {
+            self.highlighted = highlighted;
+            update_screen = true;
+        }
         // if train times were different than what's on the display, update display
         if update_screen {
             self.clear_display(false)?;
@@ -72,37 +144,218 @@ impl ScreenDisplay {
             let text_style = TextStyleBuilder::new(Font12x16)
                 .text_color(BinaryColor::On)
                 .build();
+            // smaller text style for the arrival/departure label
+            let label_style = TextStyleBuilder::new(Font6x8)
+                .text_color(BinaryColor::On)
+                .build();
+            // inverted label style to highlight a newly assigned track
+            let highlight_style = TextStyleBuilder::new(Font6x8)
+                .text_color(BinaryColor::Off)
+                .background_color(BinaryColor::On)
+                .build();
             // if there is a train1, display train time
-            if let Some(train1) = self.train1 {
-                let time = train1.format("%H:%M").to_string();
+            if let Some(train1) = &self.train1 {
+                let time = train1.time.format("%H:%M").to_string();
                 // creates text buffer
-                Text::new(&time, Point::new(35, 5))
+                Text::new(&time, Point::new(35, 0))
                     .into_styled(text_style)
                     .draw(&mut self.display)
                     .unwrap();
+                // label whether the time is the arrival or departure
+                Text::new(time_label(train1), Point::new(5, 0))
+                    .into_styled(label_style)
+                    .draw(&mut self.display)
+                    .unwrap();
+                if let Some(occupancy) = train1.occupancy {
+                    draw_occupancy(&mut self.display, occupancy, 0);
+                }
+                // track the train leaves from, once assigned
+                if let Some(track) = &train1.track {
+                    let style = if self.highlighted[0] {
+                        highlight_style
+                    } else {
+                        label_style
+                    };
+                    Text::new(&format!("T{}", track), Point::new(5, 8))
+                        .into_styled(style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
+                // label which route the train is on
+                if self.show_route {
+                    Text::new(&route_label(&train1.route), Point::new(100, 0))
+                        .into_styled(label_style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
+                // mark times from the offline schedule, which have no predictions, otherwise
+                // show how late or early the prediction is
+                if let Some(status) = status(train1) {
+                    Text::new(&status, Point::new(100, 8))
+                        .into_styled(label_style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
                 // displays text buffer
                 self.display.flush().unwrap();
             }
             // if there is a train2, display train time
-            if let Some(train2) = self.train2 {
-                let time = train2.format("%H:%M").to_string();
+            if let Some(train2) = &self.train2 {
+                let time = train2.time.format("%H:%M").to_string();
                 // creats text buffer
-                Text::new(&time, Point::new(35, 25))
+                Text::new(&time, Point::new(35, 18))
                     .into_styled(text_style)
                     .draw(&mut self.display)
                     .unwrap();
+                Text::new(time_label(train2), Point::new(5, 18))
+                    .into_styled(label_style)
+                    .draw(&mut self.display)
+                    .unwrap();
+                if let Some(occupancy) = train2.occupancy {
+                    draw_occupancy(&mut self.display, occupancy, 18);
+                }
+                if let Some(track) = &train2.track {
+                    let style = if self.highlighted[1] {
+                        highlight_style
+                    } else {
+                        label_style
+                    };
+                    Text::new(&format!("T{}", track), Point::new(5, 26))
+                        .into_styled(style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
+                if self.show_route {
+                    Text::new(&route_label(&train2.route), Point::new(100, 18))
+                        .into_styled(label_style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
+                if let Some(status) = status(train2) {
+                    Text::new(&status, Point::new(100, 26))
+                        .into_styled(label_style)
+                        .draw(&mut self.display)
+                        .unwrap();
+                }
                 // displays text buffer
                 self.display.flush().unwrap();
             }
+            // when to leave for train1, under the departure times
+            Text::new(&leave_text(leave_min), Point::new(5, 36))
+                .into_styled(label_style)
+                .draw(&mut self.display)
+                .unwrap();
+            self.display.flush().unwrap();
+            // arrival times at the destination, when one is given, below the train times
+            let arrivals = [&self.train1, &self.train2]
+                .iter()
+                .filter_map(|train| train.as_ref()?.destination_arrival)
+                .map(|arrival| arrival.format("%H:%M").to_string())
+                .collect::<Vec<String>>();
+            if !arrivals.is_empty() {
+                let arrival_text = arrival_text(self.destination_name.as_deref(), &arrivals);
+                Text::new(&arrival_text, Point::new(5, 45))
+                    .into_styled(label_style)
+                    .draw(&mut self.display)
+                    .unwrap();
+                self.display.flush().unwrap();
+            }
+            // where the next train is, or otherwise the direction, cut to the width of the screen
+            let bottom_text = match &self.position {
+                Some(position) => position.description(),
+                None => self.direction_label.clone(),
+            };
+            if !bottom_text.is_empty() {
+                let bottom_text = bottom_text.chars().take(20).collect::<String>();
+                Text::new(&bottom_text, Point::new(5, 54))
+                    .into_styled(label_style)
+                    .draw(&mut self.display)
+                    .unwrap();
+                self.display.flush().unwrap();
+            }
         }
         Ok(())
     }
 
+    /// Records the track of each train, noting when a train already shown is given a new track
+    fn update_tracks(&mut self, train_times: &[TrainTime]) {
+        let now = Instant::now();
+        for train in train_times {
+            if let Some(previous_track) = self.tracks.get(&train.trip_id) {
+                if train.track.is_some() && *previous_track != train.track {
+                    self.track_assigned.insert(train.trip_id.clone(), now);
+                }
+            }
+        }
+        self.tracks = train_times
+            .iter()
+            .map(|train| (train.trip_id.clone(), train.track.clone()))
+            .collect();
+        // stop highlighting after a while, or once the train is gone
+        let tracks = &self.tracks;
+        self.track_assigned.retain(|trip_id, assigned| {
+            tracks.contains_key(trip_id)
+                && now.duration_since(*assigned).as_secs() < TRACK_HIGHLIGHT_SECS
+        });
+    }
+
+    /// Whether the train's track was assigned recently enough to be highlighted
+    fn track_highlighted(&self, train: &Option<TrainTime>) -> bool {
+        train
+            .as_ref()
+            .is_some_and(|train| self.track_assigned.contains_key(&train.trip_id))
+    }
+
+    /// Switches to showing another countdown, such as when the scheduled profile changes.  The
+    /// trains are redrawn on the next update
+    pub fn set_countdown(
+        &mut self,
+        show_route: bool,
+        destination_name: Option<String>,
+        direction_label: String,
+        walk_min: i64,
+    ) {
+        self.show_route = show_route;
+        self.destination_name = destination_name;
+        self.direction_label = direction_label;
+        self.walk_min = walk_min;
+        self.tracks.clear();
+        self.track_assigned.clear();
+        self.position = None;
+        self.train1 = None;
+        self.train2 = None;
+        self.leave_min = None;
+    }
+
+    /// Shows the name of the profile being switched to in place of the trains
+    pub fn display_profile(&mut self, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.clear_display(true)?;
+        let label_style = TextStyleBuilder::new(Font6x8)
+            .text_color(BinaryColor::On)
+            .build();
+        let text_style = TextStyleBuilder::new(Font12x16)
+            .text_color(BinaryColor::On)
+            .build();
+        Text::new("Profile", Point::new(5, 10))
+            .into_styled(label_style)
+            .draw(&mut self.display)
+            .unwrap();
+        // 10 characters of the large font fit across the screen
+        let name = profile.chars().take(10).collect::<String>();
+        Text::new(&name, Point::new(5, 25))
+            .into_styled(text_style)
+            .draw(&mut self.display)
+            .unwrap();
+        self.display.flush().unwrap();
+        Ok(())
+    }
+
     /// Function to clear screen display
     pub fn clear_display(&mut self, reset_trains: bool) -> Result<(), Box<dyn std::error::Error>> {
         if reset_trains {
             self.train1 = None;
             self.train2 = None;
+            self.leave_min = None;
         }
         // clears the buffer
         self.display.clear();
@@ -111,3 +364,86 @@ impl ScreenDisplay {
         Ok(())
     }
 }
+
+/// Whether the time is the arrival or departure, ie "Dep".  Times without a known type are
+/// labelled as departures, as the leave line under them counts down to catching the train
+fn time_label(train: &TrainTime) -> &'static str {
+    match train.time_type {
+        TimeType::Best => TimeType::Departure.label(),
+        time_type => time_type.label(),
+    }
+}
+
+/// When to leave for the next train, ie "Leave in 7 min"
+fn leave_text(leave_min: i64) -> String {
+    match leave_min {
+        0 => "Leave now".to_string(),
+        1 => "Leave in 1 min".to_string(),
+        _ => format!("Leave in {} min", leave_min),
+    }
+}
+
+/// Draws a gauge between the label and the time, filled from the bottom by how crowded the
+/// vehicle is.  `top` is the top of the row
+fn draw_occupancy(display: &mut GraphicsMode<I2CInterface<I2c>>, occupancy: Occupancy, top: i32) {
+    // outline of the gauge, 16 pixels tall with 3 pixels for each of the 4 bars inside
+    Rectangle::new(Point::new(25, top), Point::new(30, top + 15))
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(display)
+        .unwrap();
+    let bars_top = top + 14 - occupancy.bars() * 3;
+    Rectangle::new(Point::new(27, bars_top + 1), Point::new(28, top + 13))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .unwrap();
+}
+
+/// The arrival times labelled with the destination, ie "Ashmont 17:05  17:17", shortening the
+/// name to fit the screen
+fn arrival_text(destination_name: Option<&str>, arrivals: &[String]) -> String {
+    let times = arrivals.join("  ");
+    // the screen fits 21 characters, leaving room for a space
+    let name_width = 20usize.saturating_sub(times.len());
+    let name = destination_name.unwrap_or("Arrive");
+    if name_width == 0 {
+        times
+    } else {
+        format!(
+            "{} {}",
+            name.chars().take(name_width).collect::<String>(),
+            times
+        )
+    }
+}
+
+/// "sch" for times from the offline schedule, or the minutes late, ie "+6", or early, ie "-2".
+/// None when the train is on time or there is nothing to compare
+fn status(train: &TrainTime) -> Option<String> {
+    if train.offline {
+        return Some("sch".to_string());
+    }
+    let delay_min = train.delay()?.num_minutes();
+    if delay_min == 0 {
+        None
+    } else {
+        Some(format!("{:+}", delay_min))
+    }
+}
+
+/// Shortens the API route code to fit next to the time, ie Green-B to GL-B or CR-Needham to Need
+pub fn route_label(route: &str) -> String {
+    if let Some(branch) = route.strip_prefix("Green-") {
+        return format!("GL-{}", branch);
+    }
+    let label = match route {
+        "Red" => "RL",
+        "Orange" => "OL",
+        "Blue" => "BL",
+        "Mattapan" => "M",
+        _ => route
+            .strip_prefix("CR-")
+            .or_else(|| route.strip_prefix("Boat-"))
+            .unwrap_or(route),
+    };
+    label.chars().take(4).collect()
+}
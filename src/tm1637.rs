@@ -1,5 +1,5 @@
+use crate::train_time::{catchable, TrainTime};
 use chrono;
-use chrono::prelude::*;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 use rppal;
 use std;
@@ -64,17 +64,25 @@ pub struct ClockDisplay {
     minutes_single: Option<usize>,
     seconds_ten: Option<usize>,
     seconds_single: Option<usize>,
+    // the colon is turned off when the time is only from the offline schedule
+    colon: bool,
+    // the last decimal point is lit when the train is running late
+    late: bool,
     brightness: usize,
 }
 
 // Functions to initialize and change clock display
 impl ClockDisplay {
-    /// Creates a new ClockDisplay struct
-    pub fn new(clock_brightness: u8) -> Result<ClockDisplay, Box<dyn std::error::Error>> {
+    /// Creates a new ClockDisplay struct on the GPIO clock and data pins
+    pub fn new(
+        clock_brightness: u8,
+        clock_pin: u8,
+        data_pin: u8,
+    ) -> Result<ClockDisplay, Box<dyn std::error::Error>> {
         // create new i2c interface
         let gpio = rppal::gpio::Gpio::new()?;
-        let clk_pin = gpio.get(22)?.into_output();
-        let dio_pin = gpio.get(27)?.into_output();
+        let clk_pin = gpio.get(clock_pin)?.into_output();
+        let dio_pin = gpio.get(data_pin)?.into_output();
         // connect the ht16k33 clock chip to i2c connection on the address
         let clock = TM1637::new(clk_pin, dio_pin);
         // return ClockDisplay struct with empty digits to be filled later
@@ -84,27 +92,29 @@ impl ClockDisplay {
             minutes_single: None,
             seconds_ten: None,
             seconds_single: None,
+            colon: true,
+            late: false,
             brightness: clock_brightness as usize,
         })
     }
 
-    /// Dispalys the minutes:seconds until the next train on the clock display
+    /// Dispalys the minutes:seconds until it is time to leave for the next train that can be
+    /// caught, `walk_min` minutes before it.  The last decimal point is lit when the train is
+    /// more than `late_threshold_min` behind schedule
     pub fn display_time_until(
         &mut self,
-        train_times: &[chrono::DateTime<Local>],
-        minimum_display_min: &i64,
+        train_times: &[TrainTime],
+        walk_min: &i64,
+        late_threshold_min: &i64,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // get now time in UTC
-        let now = chrono::Local::now();
-        // get the difference between now and the train time
-        let mut diff = train_times[0].signed_duration_since(now);
-        // if difference is less than minumum display, use next train
-        if diff.num_minutes() < *minimum_display_min {
-            if train_times.len() > 1usize {
-                diff = train_times[1].signed_duration_since(now)
-            } else {
-                // if there is not a next train, clear display and end
-                if vec![
+        let now = chrono::Utc::now();
+        // count down to when to leave for the first train that can still be caught
+        let train = match catchable(train_times, *walk_min, now).into_iter().next() {
+            Some(train) => train,
+            None => {
+                // if there is not a train to catch, clear display and end
+                if [
                     self.minutes_ten,
                     self.minutes_single,
                     self.seconds_ten,
@@ -117,13 +127,17 @@ impl ClockDisplay {
                 };
                 return Ok(());
             }
-        }
+        };
+        let diff = train.leave_time(*walk_min).signed_duration_since(now);
         // separate out minutes and seconds for the display
         let minutes = diff.num_minutes();
         // Seconds as the remainder after minutes are removed
         let seconds = diff.num_seconds() % 60i64;
+        let late = train
+            .delay()
+            .is_some_and(|delay| delay.num_minutes() > *late_threshold_min);
         // Clock display only has two digits for minutes, so minutes need to be below 100
-        if *minimum_display_min < minutes && minutes < 100i64 {
+        if minutes < 100i64 {
             // find all of the new digits for displaying difference
             // first digit, which is the tens minutes
             let first = (minutes as usize) / 10usize;
@@ -134,7 +148,7 @@ impl ClockDisplay {
             // fourth digit, which is the seconds single
             let fourth = (seconds as usize) % 10usize;
             // if current display has no values, then display all of the new values
-            if vec![
+            if [
                 self.minutes_ten,
                 self.minutes_single,
                 self.seconds_ten,
@@ -147,6 +161,8 @@ impl ClockDisplay {
                 self.minutes_single = Some(second);
                 self.seconds_ten = Some(third);
                 self.seconds_single = Some(fourth);
+                self.colon = !train.offline;
+                self.late = late;
                 self.display_nums()?;
             } else {
                 // else change only the values that have changed
@@ -157,12 +173,11 @@ impl ClockDisplay {
                         .unwrap();
                     self.minutes_ten = Some(first);
                 }
-                if Some(second) != self.minutes_single {
-                    let mut bin_colon = BINS[second as usize];
-                    // add the colon with the first bit
-                    bin_colon |= 0b10000000;
+                // the colon shares the second digit, so it is rewritten when the colon changes
+                if Some(second) != self.minutes_single || train.offline == self.colon {
+                    self.colon = !train.offline;
                     self.display
-                        .print_raw(DISPLAY_ADDRESS[1], bin_colon)
+                        .print_raw(DISPLAY_ADDRESS[1], self.colon_digit(second))
                         .unwrap();
                     self.minutes_single = Some(second);
                 }
@@ -172,9 +187,11 @@ impl ClockDisplay {
                         .unwrap();
                     self.seconds_ten = Some(third);
                 }
-                if Some(fourth) != self.seconds_single {
+                // the decimal point shares the last digit, so it is rewritten when it changes
+                if Some(fourth) != self.seconds_single || late != self.late {
+                    self.late = late;
                     self.display
-                        .print_raw(DISPLAY_ADDRESS[3], BINS[fourth])
+                        .print_raw(DISPLAY_ADDRESS[3], self.decimal_point_digit(fourth))
                         .unwrap();
                     self.seconds_single = Some(fourth);
                 }
@@ -228,11 +245,8 @@ impl ClockDisplay {
                 .unwrap();
         }
         if let Some(minutes_single) = self.minutes_single {
-            let mut bin_colon = BINS[minutes_single as usize];
-            // add the colon with the first bit
-            bin_colon |= 0b10000000;
             self.display
-                .print_raw(DISPLAY_ADDRESS[1], bin_colon)
+                .print_raw(DISPLAY_ADDRESS[1], self.colon_digit(minutes_single))
                 .unwrap();
         }
         if let Some(seconds_ten) = self.seconds_ten {
@@ -242,7 +256,7 @@ impl ClockDisplay {
         }
         if let Some(seconds_single) = self.seconds_single {
             self.display
-                .print_raw(DISPLAY_ADDRESS[3], BINS[seconds_single])
+                .print_raw(DISPLAY_ADDRESS[3], self.decimal_point_digit(seconds_single))
                 .unwrap();
         }
         self.display
@@ -250,6 +264,25 @@ impl ClockDisplay {
             .unwrap();
         Ok(())
     }
+
+    /// Segments for the last digit, with the decimal point added if the train is late
+    fn decimal_point_digit(&self, digit: usize) -> u8 {
+        if self.late {
+            BINS[digit] | 0b1000_0000
+        } else {
+            BINS[digit]
+        }
+    }
+
+    /// Segments for the digit before the colon, with the colon added if it is on
+    fn colon_digit(&self, digit: usize) -> u8 {
+        if self.colon {
+            // add the colon with the first bit
+            BINS[digit] | 0b10000000
+        } else {
+            BINS[digit]
+        }
+    }
 }
 
 #[derive(Debug)]
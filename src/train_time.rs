@@ -1,60 +1,205 @@
-use chrono::prelude::*;
-use chrono::{DateTime, Local, TimeZone};
-use reqwest;
+use crate::mbta_info::get_api_json;
+use crate::service_date::{date_filter, min_time_filter};
+use crate::transit_provider::{Alert, VehiclePosition};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
 use serde_json::Value;
 use std;
 use std::{collections::HashMap, error::Error};
 
-// Main function to retrieve train times from Forest Hills Station for inbound commuter rail
+/// Which of the MBTA arrival or departure times to count down to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeType {
+    Arrival,
+    Departure,
+    /// Departure time, falling back to the arrival time when there is no departure, such as
+    /// the last stop of a trip
+    Best,
+}
+
+impl TimeType {
+    /// Label shown on the screen next to the time
+    pub fn label(&self) -> &'static str {
+        match self {
+            TimeType::Arrival => "Arr",
+            TimeType::Departure => "Dep",
+            TimeType::Best => "",
+        }
+    }
+}
+
+/// How crowded a vehicle is, from least to most crowded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Occupancy {
+    ManySeats,
+    FewSeats,
+    StandingOnly,
+    Full,
+}
+
+impl Occupancy {
+    /// Converts the MBTA occupancy_status, which follows GTFS-Realtime
+    pub fn from_status(status: &str) -> Option<Occupancy> {
+        match status {
+            "EMPTY" | "MANY_SEATS_AVAILABLE" => Some(Occupancy::ManySeats),
+            "FEW_SEATS_AVAILABLE" => Some(Occupancy::FewSeats),
+            "STANDING_ROOM_ONLY" | "CRUSHED_STANDING_ROOM_ONLY" => Some(Occupancy::StandingOnly),
+            "FULL" | "NOT_ACCEPTING_PASSENGERS" => Some(Occupancy::Full),
+            _ => None,
+        }
+    }
+
+    /// Number of bars, out of 4, to fill on the screen
+    pub fn bars(&self) -> i32 {
+        match self {
+            Occupancy::ManySeats => 1,
+            Occupancy::FewSeats => 2,
+            Occupancy::StandingOnly => 3,
+            Occupancy::Full => 4,
+        }
+    }
+}
+
+/// Number of upcoming trains to look up the occupancy of
+const OCCUPANCY_TRAINS: usize = 4;
+
+/// A single vehicle time at the station
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainTime {
+    /// Predicted time, or the scheduled time when there is no prediction
+    pub time: DateTime<Tz>,
+    pub scheduled: Option<DateTime<Tz>>,
+    pub predicted: Option<DateTime<Tz>>,
+    /// API route code of the vehicle, ie CR-Needham, to tell routes apart when several are merged
+    pub route: String,
+    /// Trip the time belongs to, used to find the same trip at other stops
+    pub trip_id: String,
+    /// Arrival time at the destination station, when one is given
+    pub destination_arrival: Option<DateTime<Tz>>,
+    /// Track or platform, ie 3 at South Station, once it has been assigned
+    pub track: Option<String>,
+    /// Position of the station within the trip, to compare with where the vehicle is
+    pub stop_sequence: Option<u64>,
+    /// How crowded the vehicle is, once it has started the trip and reports it
+    pub occupancy: Option<Occupancy>,
+    /// Whether the time is the arrival or departure time.  Never `TimeType::Best`
+    pub time_type: TimeType,
+    /// Time comes from the offline GTFS schedule because the live feed could not be reached, so
+    /// it is scheduled only without any predictions
+    pub offline: bool,
+}
+
+impl TrainTime {
+    /// How far the prediction is behind the schedule, negative when early.  None unless there are
+    /// both scheduled and predicted times
+    pub fn delay(&self) -> Option<Duration> {
+        Some(self.predicted? - self.scheduled?)
+    }
+
+    /// When to leave to catch the train, `walk_min` minutes before its time
+    pub fn leave_time(&self, walk_min: i64) -> DateTime<Tz> {
+        self.time - Duration::minutes(walk_min)
+    }
+}
+
+/// The trains that can still be caught when leaving now, with `walk_min` minutes to walk to the
+/// station, in the same order
+pub fn catchable(train_times: &[TrainTime], walk_min: i64, now: DateTime<Utc>) -> Vec<TrainTime> {
+    train_times
+        .iter()
+        .filter(|train| train.leave_time(walk_min) >= now)
+        .cloned()
+        .collect()
+}
+
+// Main function to retrieve train times from Forest Hills Station for inbound commuter rail.
+// Several stations and routes can be given, which are merged into one list.  With a destination,
+// only the trains which stop there afterwards are kept.  With skip_full, trains reported as full
+// are left out
 pub async fn train_times(
     dir_code: &str,
-    station: &str,
-    route_code: &str,
-) -> Result<Option<Vec<DateTime<Local>>>, Box<dyn Error>> {
+    stations: &[String],
+    route_codes: &[String],
+    destinations: &[String],
+    skip_full: bool,
+    time_type: TimeType,
+    timezone: Tz,
+) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>> {
+    // the API accepts comma separated lists for the filters
+    let station = stations.join(",");
+    let route_code = route_codes.join(",");
     // get prediction times
-    let prediction_times_task = get_prediction_times(station, dir_code, route_code);
+    let prediction_times_task =
+        get_prediction_times(&station, dir_code, &route_code, time_type, timezone);
     // get schuduled times, if None, create empty hashmap
-    let scheduled_times_task = get_scheduled_times(station, dir_code, route_code, true);
+    let scheduled_times_task =
+        get_scheduled_times(&station, dir_code, &route_code, true, time_type, timezone);
     let prediction_times = prediction_times_task.await?;
     let mut scheduled_times = scheduled_times_task.await?.unwrap_or_default();
-    // let (prediction_times, scheduled_times_start) = try_join!(prediction_times_task, scheduled_times_task)?;
-    // let mut scheduled_times = scheduled_times_start.unwrap_or(HashMap::new());
     // if there are predicted times, replace the scheduled times with the more accurate predicted
     // tiem
     if let Some(pred_times) = prediction_times {
-        for key in pred_times.keys() {
-            if scheduled_times.keys().any(|schud_key| schud_key == key) {
-                *scheduled_times.get_mut(key).unwrap() = pred_times[key]
-            } else {
-                scheduled_times.insert(key.clone(), pred_times[key]);
+        for (trip_id, mut pred_time) in pred_times {
+            // keep the scheduled time along with the prediction to show the delay.  The
+            // prediction includes its schedule, which is still there for trains running late
+            if let Some(sched_time) = scheduled_times.get(&trip_id) {
+                if pred_time.scheduled.is_none() && sched_time.time_type == pred_time.time_type {
+                    pred_time.scheduled = sched_time.scheduled;
+                }
+                if pred_time.track.is_none() {
+                    pred_time.track = sched_time.track.clone();
+                }
             }
+            scheduled_times.insert(trip_id, pred_time);
         }
     }
     // get the current time and filter out any train time before now
-    let now = Local::now();
+    let now = Utc::now().with_timezone(&timezone);
     let mut all_times = scheduled_times
-        .values()
-        .filter_map(|date| if date > &now { Some(*date) } else { None })
-        .collect::<Vec<DateTime<Local>>>();
-    all_times.sort();
-    //    println!("{:?}", all_times);
+        .into_values()
+        .filter(|train| train.time > now)
+        .collect::<Vec<TrainTime>>();
+    all_times.sort_by_key(|train| train.time);
+    if !destinations.is_empty() {
+        all_times = reaching_destination(all_times, destinations, timezone).await?;
+    }
+    // occupancy is extra information, so the times are still shown if it cannot be retrieved
+    if let Ok(occupancies) = get_occupancies(&all_times).await {
+        for train in all_times.iter_mut() {
+            train.occupancy = occupancies.get(&train.trip_id).copied();
+        }
+    }
+    if skip_full {
+        all_times.retain(|train| train.occupancy != Some(Occupancy::Full));
+    }
     if all_times.is_empty() {
         return Ok(None);
     }
     Ok(Some(all_times))
 }
 
+/// Retrieves the last and first scheduled times of the current MBTA service date
 pub async fn max_min_times(
     dir_code: &str,
-    station: &str,
-    route_code: &str,
-) -> Result<Option<[DateTime<Local>; 2]>, Box<dyn Error>> {
-    if let Some(scheduled_times) = get_scheduled_times(station, dir_code, route_code, false).await?
+    stations: &[String],
+    route_codes: &[String],
+    time_type: TimeType,
+    timezone: Tz,
+) -> Result<Option<[DateTime<Tz>; 2]>, Box<dyn Error>> {
+    if let Some(scheduled_times) = get_scheduled_times(
+        &stations.join(","),
+        dir_code,
+        &route_codes.join(","),
+        false,
+        time_type,
+        timezone,
+    )
+    .await?
     {
         let mut all_times = scheduled_times
             .values()
-            .copied()
-            .collect::<Vec<DateTime<Local>>>();
+            .map(|train| train.time)
+            .collect::<Vec<DateTime<Tz>>>();
         all_times.sort();
         if let Some(last_vehicle) = all_times.last() {
             Ok(Some([*last_vehicle, all_times[0]]))
@@ -71,10 +216,12 @@ async fn get_prediction_times(
     station: &str,
     dir_code: &str,
     route_code: &str,
-) -> Result<Option<HashMap<String, DateTime<Local>>>, Box<dyn Error>> {
+    time_type: TimeType,
+    timezone: Tz,
+) -> Result<Option<HashMap<String, TrainTime>>, Box<dyn Error>> {
     // MBTA API for predicted times
-    let address = format!("https://api-v3.mbta.com/predictions?filter[stop]={}&filter[direction_id]={}&include=stop&filter[route]={}", station, dir_code, route_code);
-    get_route_times(address).await
+    let address = format!("https://api-v3.mbta.com/predictions?filter[stop]={}&filter[direction_id]={}&include=stop,schedule&filter[route]={}", station, dir_code, route_code);
+    get_route_times(address, time_type, true, timezone).await
 }
 
 /// Retreived MBTA scheduled times with their API
@@ -83,45 +230,122 @@ async fn get_scheduled_times(
     dir_code: &str,
     route_code: &str,
     filter_time: bool,
-) -> Result<Option<HashMap<String, DateTime<Local>>>, Box<dyn std::error::Error>> {
-    let address;
-    if filter_time {
-        let now = chrono::Local::now();
+    time_type: TimeType,
+    timezone: Tz,
+) -> Result<Option<HashMap<String, TrainTime>>, Box<dyn std::error::Error>> {
+    // schedules are filtered by the MBTA service date so that trains after midnight are still
+    // included with the previous day's service
+    let now = Utc::now().with_timezone(&timezone);
+    let address = if filter_time {
         // MBTA API for scheduled times
-        address = format!("https://api-v3.mbta.com/schedules?include=route,trip,stop&filter[min_time]={}%3A{}&filter[stop]={}&filter[route]={}&filter[direction_id]={}",now.hour(), now.minute(), station, route_code, dir_code);
+        format!("https://api-v3.mbta.com/schedules?include=route,trip,stop&filter[date]={}&filter[min_time]={}&filter[stop]={}&filter[route]={}&filter[direction_id]={}", date_filter(&now), min_time_filter(&now).replace(':', "%3A"), station, route_code, dir_code)
     } else {
-        address = format!("https://api-v3.mbta.com/schedules?include=route,trip,stop&filter[stop]={}&filter[route]={}&filter[direction_id]={}", station, route_code, dir_code);
-    }
-    get_route_times(address).await
+        format!("https://api-v3.mbta.com/schedules?include=route,trip,stop&filter[date]={}&filter[stop]={}&filter[route]={}&filter[direction_id]={}", date_filter(&now), station, route_code, dir_code)
+    };
+    get_route_times(address, time_type, false, timezone).await
 }
 
-/// Retreives the JSON from MBTA API and parses it into a hasmap
+/// Retreives the JSON from MBTA API and parses it into a hasmap.  `predicted` is whether the
+/// address is for predictions rather than schedules
 async fn get_route_times(
     address: String,
-) -> Result<Option<HashMap<String, DateTime<Local>>>, Box<dyn Error>> {
+    time_type: TimeType,
+    predicted: bool,
+    timezone: Tz,
+) -> Result<Option<HashMap<String, TrainTime>>, Box<dyn Error>> {
     // retrieve the routes with the MBTA API returning a converted JSON format
-    let routes_json: Value = reqwest::get(&address).await?.json().await?;
+    let routes_json = get_api_json(&address).await?;
+    parse_route_times(&routes_json, time_type, predicted, timezone)
+}
+
+/// Parses the JSON from the MBTA API into a hashmap of trip id -> train time.  Predictions
+/// including their schedule keep the scheduled time, even when it has already passed
+fn parse_route_times(
+    routes_json: &Value,
+    time_type: TimeType,
+    predicted: bool,
+    timezone: Tz,
+) -> Result<Option<HashMap<String, TrainTime>>, Box<dyn Error>> {
     // only interested in the "data" field
     let data_option = routes_json.get("data");
     // if there is a "data" field, proceed
     if let Some(data) = data_option {
         // if the "data" field is an array, proceed
         if let Some(data_array) = data.as_array() {
+            // included stops give the track, or platform, of the stop each time is for
+            let tracks = included_tracks(routes_json);
+            // included schedules give the scheduled times of predictions
+            let schedules = included(routes_json, "schedule");
             // create a new HashMap to put int trip_id and departure time
-            let mut commuter_rail_dep_time: HashMap<String, DateTime<Local>> = HashMap::new();
+            let mut commuter_rail_dep_time: HashMap<String, TrainTime> = HashMap::new();
             // for each train in the data array, insert the trip_id and departure time
             for train in data_array {
+                let arrival_time_option = train["attributes"]["arrival_time"].as_str();
                 let departure_time_option = train["attributes"]["departure_time"].as_str();
                 let trip_id_option = train["relationships"]["trip"]["data"]["id"].as_str();
+                let route = train["relationships"]["route"]["data"]["id"]
+                    .as_str()
+                    .unwrap_or_default();
+                let stop_sequence = train["attributes"]["stop_sequence"].as_u64();
+                let track = train["relationships"]["stop"]["data"]["id"]
+                    .as_str()
+                    .and_then(|stop_id| tracks.get(stop_id))
+                    .cloned();
+                // pick the arrival or departure time depending on what is requested.  The first
+                // stop of a trip has no arrival time and the last stop has no departure time
+                let time_option = match time_type {
+                    TimeType::Arrival => arrival_time_option.map(|time| (time, TimeType::Arrival)),
+                    TimeType::Departure => {
+                        departure_time_option.map(|time| (time, TimeType::Departure))
+                    }
+                    TimeType::Best => departure_time_option
+                        .map(|time| (time, TimeType::Departure))
+                        .or_else(|| arrival_time_option.map(|time| (time, TimeType::Arrival))),
+                };
                 // if there is a trip id
                 if let Some(trip_id) = trip_id_option {
-                    // and if there is a departure time for the train
-                    if let Some(departure_time) = departure_time_option {
-                        // convert departure time to DateTime<Local>
-                        let departure_time_datetime =
-                            Local.datetime_from_str(departure_time, "%+")?;
+                    // and if there is a time for the train
+                    if let Some((time, found_type)) = time_option {
+                        // convert the time to the agency timezone
+                        let time_datetime = parse_api_time(time, timezone)?;
+                        // when a trip stops at more than one of the stations, keep the earliest
+                        if commuter_rail_dep_time
+                            .get(trip_id)
+                            .is_some_and(|train| train.time <= time_datetime)
+                        {
+                            continue;
+                        }
+                        let scheduled = if predicted {
+                            let attribute = match found_type {
+                                TimeType::Arrival => "arrival_time",
+                                _ => "departure_time",
+                            };
+                            train["relationships"]["schedule"]["data"]["id"]
+                                .as_str()
+                                .and_then(|schedule_id| schedules.get(schedule_id))
+                                .and_then(|schedule| schedule["attributes"][attribute].as_str())
+                                .map(|time| parse_api_time(time, timezone))
+                                .transpose()?
+                        } else {
+                            Some(time_datetime)
+                        };
                         // insert into HashMap
-                        commuter_rail_dep_time.insert(trip_id.to_string(), departure_time_datetime);
+                        commuter_rail_dep_time.insert(
+                            trip_id.to_string(),
+                            TrainTime {
+                                time: time_datetime,
+                                scheduled,
+                                predicted: predicted.then_some(time_datetime),
+                                route: route.to_string(),
+                                trip_id: trip_id.to_string(),
+                                destination_arrival: None,
+                                track,
+                                stop_sequence,
+                                occupancy: None,
+                                time_type: found_type,
+                                offline: false,
+                            },
+                        );
                     }
                 }
             }
@@ -134,3 +358,359 @@ async fn get_route_times(
         Ok(None)
     }
 }
+
+/// Id -> object of the included objects of a type in the API response
+fn included<'a>(routes_json: &'a Value, object_type: &str) -> HashMap<&'a str, &'a Value> {
+    routes_json["included"]
+        .as_array()
+        .map(|included_array| {
+            included_array
+                .iter()
+                .filter(|included| included["type"].as_str() == Some(object_type))
+                .filter_map(|included| Some((included["id"].as_str()?, included)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Stop id -> platform code of the stops included with the API response.  Only the platforms with
+/// a code, such as the commuter rail tracks, are returned
+fn included_tracks(routes_json: &Value) -> HashMap<String, String> {
+    let mut tracks = HashMap::new();
+    if let Some(included_array) = routes_json["included"].as_array() {
+        for included in included_array {
+            if included["type"].as_str() != Some("stop") {
+                continue;
+            }
+            if let (Some(stop_id), Some(platform_code)) = (
+                included["id"].as_str(),
+                included["attributes"]["platform_code"].as_str(),
+            ) {
+                tracks.insert(stop_id.to_string(), platform_code.to_string());
+            }
+        }
+    }
+    tracks
+}
+
+/// Keeps only the trains whose trips arrive at one of the destination stops after leaving the
+/// station, along with their predicted, or else scheduled, arrival time there
+async fn reaching_destination(
+    train_times: Vec<TrainTime>,
+    destinations: &[String],
+    timezone: Tz,
+) -> Result<Vec<TrainTime>, Box<dyn Error>> {
+    if train_times.is_empty() {
+        return Ok(train_times);
+    }
+    let destination = destinations.join(",");
+    let trip_ids = train_times
+        .iter()
+        .map(|train| train.trip_id.as_str())
+        .collect::<Vec<&str>>()
+        .join(",");
+    let prediction_address = format!(
+        "https://api-v3.mbta.com/predictions?filter[stop]={}&filter[trip]={}",
+        destination, trip_ids
+    );
+    let schedule_address = format!(
+        "https://api-v3.mbta.com/schedules?filter[stop]={}&filter[trip]={}",
+        destination, trip_ids
+    );
+    let prediction_times =
+        get_route_times(prediction_address, TimeType::Arrival, true, timezone).await?;
+    let mut arrivals = get_route_times(schedule_address, TimeType::Arrival, false, timezone)
+        .await?
+        .unwrap_or_default();
+    if let Some(pred_times) = prediction_times {
+        arrivals.extend(pred_times);
+    }
+    let arrivals = arrivals
+        .into_iter()
+        .map(|(trip_id, train)| (trip_id, train.time))
+        .collect();
+    Ok(filter_destination(train_times, &arrivals))
+}
+
+/// Keeps the trains with an arrival at the destination later than their time at the station,
+/// setting their destination arrival.  `arrivals` is trip id -> arrival time at the destination
+pub fn filter_destination(
+    train_times: Vec<TrainTime>,
+    arrivals: &HashMap<String, DateTime<Tz>>,
+) -> Vec<TrainTime> {
+    train_times
+        .into_iter()
+        .filter_map(|mut train| {
+            let arrival = *arrivals.get(&train.trip_id)?;
+            // a trip arriving at the destination first is heading away from it
+            if arrival <= train.time {
+                return None;
+            }
+            train.destination_arrival = Some(arrival);
+            Some(train)
+        })
+        .collect()
+}
+
+/// Trip id -> occupancy of the vehicles running the next few trains.  For vehicles reporting each
+/// carriage, the least crowded carriage is used as riders can walk to it
+async fn get_occupancies(
+    train_times: &[TrainTime],
+) -> Result<HashMap<String, Occupancy>, Box<dyn Error>> {
+    let mut occupancies = HashMap::new();
+    if train_times.is_empty() {
+        return Ok(occupancies);
+    }
+    let trip_ids = train_times
+        .iter()
+        .take(OCCUPANCY_TRAINS)
+        .map(|train| train.trip_id.as_str())
+        .collect::<Vec<&str>>()
+        .join(",");
+    let address = format!("https://api-v3.mbta.com/vehicles?filter[trip]={}", trip_ids);
+    let vehicles_json = get_api_json(&address).await?;
+    if let Some(vehicles) = vehicles_json["data"].as_array() {
+        for vehicle in vehicles {
+            let trip_id = match vehicle["relationships"]["trip"]["data"]["id"].as_str() {
+                Some(trip_id) => trip_id,
+                None => continue,
+            };
+            let carriage_occupancy =
+                vehicle["attributes"]["carriages"]
+                    .as_array()
+                    .and_then(|carriages| {
+                        carriages
+                            .iter()
+                            .filter_map(|carriage| carriage["occupancy_status"].as_str())
+                            .filter_map(Occupancy::from_status)
+                            .min()
+                    });
+            let occupancy = carriage_occupancy.or_else(|| {
+                vehicle["attributes"]["occupancy_status"]
+                    .as_str()
+                    .and_then(Occupancy::from_status)
+            });
+            if let Some(occupancy) = occupancy {
+                occupancies.insert(trip_id.to_string(), occupancy);
+            }
+        }
+    }
+    Ok(occupancies)
+}
+
+/// Finds where the vehicle running the train's trip is with the MBTA vehicles API
+pub async fn vehicle_position(
+    train: &TrainTime,
+) -> Result<Option<VehiclePosition>, Box<dyn Error>> {
+    let station_sequence = match train.stop_sequence {
+        Some(station_sequence) => station_sequence,
+        None => return Ok(None),
+    };
+    let address = format!(
+        "https://api-v3.mbta.com/vehicles?filter[trip]={}&include=stop",
+        train.trip_id
+    );
+    let vehicles_json = get_api_json(&address).await?;
+    // the vehicle is only listed once it has started the trip
+    let vehicle = &vehicles_json["data"][0];
+    let current_sequence = match vehicle["attributes"]["current_stop_sequence"].as_u64() {
+        Some(current_sequence) => current_sequence,
+        None => return Ok(None),
+    };
+    // the vehicle has already left the station
+    if current_sequence > station_sequence {
+        return Ok(None);
+    }
+    if vehicle["attributes"]["current_status"].as_str() == Some("STOPPED_AT") {
+        let stop_name = vehicles_json["included"][0]["attributes"]["name"]
+            .as_str()
+            .unwrap_or_default();
+        return Ok(Some(VehiclePosition::StoppedAt(stop_name.to_string())));
+    }
+    // stop sequences are not always consecutive, so the stops are counted from the trip schedule
+    let address = format!(
+        "https://api-v3.mbta.com/schedules?filter[trip]={}",
+        train.trip_id
+    );
+    let schedules_json = get_api_json(&address).await?;
+    let stops_away = schedules_json["data"]
+        .as_array()
+        .map(|schedules| {
+            schedules
+                .iter()
+                .filter_map(|schedule| schedule["attributes"]["stop_sequence"].as_u64())
+                .filter(|sequence| current_sequence <= *sequence && *sequence < station_sequence)
+                .count()
+        })
+        .unwrap_or_default();
+    Ok(Some(VehiclePosition::StopsAway(stops_away as u32)))
+}
+
+/// Retrieves the MBTA service alerts currently affecting the routes at the stations
+pub async fn alerts(
+    dir_code: &str,
+    stations: &[String],
+    route_codes: &[String],
+) -> Result<Vec<Alert>, Box<dyn Error>> {
+    let address = format!("https://api-v3.mbta.com/alerts?filter[route]={}&filter[stop]={}&filter[direction_id]={}&filter[datetime]=NOW", route_codes.join(","), stations.join(","), dir_code);
+    let alerts_json = get_api_json(&address).await?;
+    let mut alerts = Vec::new();
+    if let Some(data_array) = alerts_json["data"].as_array() {
+        for alert in data_array {
+            if let Some(header) = alert["attributes"]["header"].as_str() {
+                alerts.push(Alert {
+                    header: header.to_string(),
+                    effect: alert["attributes"]["effect"]
+                        .as_str()
+                        .unwrap_or("UNKNOWN_EFFECT")
+                        .to_string(),
+                })
+            }
+        }
+    }
+    Ok(alerts)
+}
+
+/// Parses a time from the MBTA API, which includes its UTC offset, into the agency timezone
+pub fn parse_api_time(time: &str, timezone: Tz) -> Result<DateTime<Tz>, Box<dyn Error>> {
+    Ok(DateTime::parse_from_rfc3339(time)?.with_timezone(&timezone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    /// Departure of a Needham Line trip at an API time
+    fn train(trip_id: &str, time: &str) -> TrainTime {
+        TrainTime {
+            time: parse_api_time(time, New_York).unwrap(),
+            scheduled: None,
+            predicted: None,
+            route: "CR-Needham".to_string(),
+            trip_id: trip_id.to_string(),
+            destination_arrival: None,
+            track: None,
+            stop_sequence: None,
+            occupancy: None,
+            time_type: TimeType::Departure,
+            offline: false,
+        }
+    }
+
+    #[test]
+    fn api_time_in_agency_timezone() {
+        let time = parse_api_time("2021-07-09T17:05:00-04:00", New_York).unwrap();
+        assert_eq!(time.format("%H:%M").to_string(), "17:05");
+        // a time given in UTC is still displayed in Boston time
+        let time = parse_api_time("2021-01-09T22:05:00Z", New_York).unwrap();
+        assert_eq!(time.format("%H:%M").to_string(), "17:05");
+    }
+
+    #[test]
+    fn api_time_across_dst() {
+        // spring forward, 2 am EST becomes 3 am EDT
+        let before = parse_api_time("2021-03-14T01:59:00-05:00", New_York).unwrap();
+        let after = parse_api_time("2021-03-14T03:01:00-04:00", New_York).unwrap();
+        assert_eq!(before.format("%H:%M").to_string(), "01:59");
+        assert_eq!(after.format("%H:%M").to_string(), "03:01");
+        assert_eq!((after - before).num_minutes(), 2);
+        // fall back, 1:30 am occurs twice
+        let first = parse_api_time("2021-11-07T01:30:00-04:00", New_York).unwrap();
+        let second = parse_api_time("2021-11-07T01:30:00-05:00", New_York).unwrap();
+        assert_eq!(first.format("%H:%M").to_string(), "01:30");
+        assert_eq!(second.format("%H:%M").to_string(), "01:30");
+        assert_eq!((second - first).num_minutes(), 60);
+    }
+
+    #[test]
+    fn trains_that_can_be_caught() {
+        let train_times = [
+            train("1", "2021-07-09T17:05:00-04:00"),
+            train("2", "2021-07-09T17:12:00-04:00"),
+            train("3", "2021-07-09T17:30:00-04:00"),
+        ];
+        let now = parse_api_time("2021-07-09T17:00:00-04:00", New_York)
+            .unwrap()
+            .with_timezone(&Utc);
+        let leave_times = |walk_min: i64| {
+            catchable(&train_times, walk_min, now)
+                .iter()
+                .map(|train| train.leave_time(walk_min).format("%H:%M").to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(leave_times(0), ["17:05", "17:12", "17:30"]);
+        // leaving right now still catches the train
+        assert_eq!(leave_times(5), ["17:00", "17:07", "17:25"]);
+        assert_eq!(leave_times(12), ["17:00", "17:18"]);
+        assert!(leave_times(31).is_empty());
+    }
+
+    #[test]
+    fn trains_reaching_destination() {
+        let train_times = vec![
+            train("inbound", "2021-07-09T17:05:00-04:00"),
+            train("outbound", "2021-07-09T17:10:00-04:00"),
+            train("short turn", "2021-07-09T17:15:00-04:00"),
+        ];
+        let mut arrivals = HashMap::new();
+        arrivals.insert(
+            "inbound".to_string(),
+            parse_api_time("2021-07-09T17:35:00-04:00", New_York).unwrap(),
+        );
+        // reaches the destination before the station, so it is heading away from it
+        arrivals.insert(
+            "outbound".to_string(),
+            parse_api_time("2021-07-09T16:40:00-04:00", New_York).unwrap(),
+        );
+        let trains = filter_destination(train_times, &arrivals);
+        assert_eq!(trains.len(), 1);
+        assert_eq!(trains[0].trip_id, "inbound");
+        assert_eq!(
+            trains[0]
+                .destination_arrival
+                .map(|time| time.format("%H:%M").to_string()),
+            Some("17:35".to_string())
+        );
+    }
+
+    #[test]
+    fn late_prediction_keeps_past_schedule() {
+        // scheduled to leave at 16:55, before now, and predicted to leave at 17:10
+        let predictions_json = serde_json::json!({
+            "data": [{
+                "type": "prediction",
+                "attributes": {
+                    "arrival_time": null,
+                    "departure_time": "2021-07-09T17:10:00-04:00",
+                    "stop_sequence": 1
+                },
+                "relationships": {
+                    "route": {"data": {"id": "CR-Needham", "type": "route"}},
+                    "schedule": {"data": {"id": "schedule-late", "type": "schedule"}},
+                    "stop": {"data": {"id": "NHRML-0127-01", "type": "stop"}},
+                    "trip": {"data": {"id": "late", "type": "trip"}}
+                }
+            }],
+            "included": [{
+                "type": "schedule",
+                "id": "schedule-late",
+                "attributes": {
+                    "arrival_time": null,
+                    "departure_time": "2021-07-09T16:55:00-04:00"
+                }
+            }]
+        });
+        let train = parse_route_times(&predictions_json, TimeType::Best, true, New_York)
+            .unwrap()
+            .unwrap()
+            .remove("late")
+            .unwrap();
+        assert_eq!(train.time.format("%H:%M").to_string(), "17:10");
+        assert_eq!(
+            train.scheduled.map(|time| time.format("%H:%M").to_string()),
+            Some("16:55".to_string())
+        );
+        assert_eq!(train.delay(), Some(Duration::minutes(15)));
+    }
+}
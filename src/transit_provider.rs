@@ -0,0 +1,339 @@
+use crate::gtfs_realtime;
+use crate::gtfs_static::GtfsIndex;
+use crate::mbta_info::{all_mbta_info, find_stop, refresh_mbta_info, Catalog};
+use crate::train_time::{self, TimeType, TrainTime};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::{error::Error, path::PathBuf};
+
+/// The routes, stations and direction to retrieve vehicle times for.  Times for all of the
+/// routes at all of the stations are merged into one countdown
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    /// API route codes, ie CR-Needham
+    pub route_codes: Vec<String>,
+    /// API station codes, ie place-forhl
+    pub stations: Vec<String>,
+    /// API station codes of the stops the rider is going to.  Only the trains stopping at one of
+    /// them are counted down, or every train when empty
+    pub destination: Vec<String>,
+    /// Leave out the trains reported as full
+    pub skip_full: bool,
+    /// API direction id, 0 or 1
+    pub dir_code: String,
+    pub time_type: TimeType,
+}
+
+/// A service alert affecting the route or station
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    /// Short summary of the alert
+    pub header: String,
+    /// What the alert does to service, ie SHUTTLE or DELAY
+    pub effect: String,
+}
+
+/// A stop found by its stop number, such as a bus stop or a station platform
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stop {
+    /// Name shown at the stop, ie "Highland Ave @ Hunting Rd"
+    pub name: String,
+    /// API station code of the station the stop is a platform of, None for a stop on its own
+    pub parent_station: Option<String>,
+    /// API route codes of the routes serving the stop
+    pub routes: Vec<String>,
+}
+
+/// Where the vehicle running a trip is, relative to the station
+#[derive(Clone, Debug, PartialEq)]
+pub enum VehiclePosition {
+    /// Moving, with the number of stops still to make before the station
+    StopsAway(u32),
+    /// Stopped at the named stop
+    StoppedAt(String),
+}
+
+impl VehiclePosition {
+    /// Text shown on the screen, ie "2 stops away" or "Stopped at Forest Hills"
+    pub fn description(&self) -> String {
+        match self {
+            VehiclePosition::StopsAway(0) => "Approaching".to_string(),
+            VehiclePosition::StopsAway(1) => "1 stop away".to_string(),
+            VehiclePosition::StopsAway(stops) => format!("{} stops away", stops),
+            VehiclePosition::StoppedAt(stop_name) => format!("Stopped at {}", stop_name),
+        }
+    }
+}
+
+/// A source of transit information.  The displays only use this trait, so that any agency can be
+/// counted down to
+#[async_trait]
+pub trait TransitProvider: Send + Sync {
+    /// The route and station information used to limit the command line input
+    ///
+    /// # Arguments
+    ///
+    ///  * `update` - whether or not to force an update of the information
+    async fn catalog(&self, update: bool) -> Result<Catalog, Box<dyn Error>>;
+
+    /// Quietly refreshes the saved route and station information, for when it is stale.  Nothing
+    /// is done by providers whose information is not saved
+    async fn refresh_catalog(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// The stop with the stop number, or None when there is no such stop.  Bus stops are not in
+    /// the catalog, so they are found with this
+    async fn stop(&self, stop_number: &str) -> Result<Option<Stop>, Box<dyn Error>>;
+
+    /// The vehicle times after now, sorted with the soonest first
+    async fn train_times(&self, query: &Query) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>>;
+
+    /// Service alerts currently affecting the routes or stations
+    async fn alerts(&self, query: &Query) -> Result<Vec<Alert>, Box<dyn Error>>;
+
+    /// Where the vehicle for the train is, or None before the vehicle starts the trip
+    async fn vehicle_position(
+        &self,
+        train: &TrainTime,
+    ) -> Result<Option<VehiclePosition>, Box<dyn Error>>;
+
+    /// The last and first vehicle times of the current service date
+    async fn service_span(
+        &self,
+        query: &Query,
+    ) -> Result<Option<[DateTime<Tz>; 2]>, Box<dyn Error>>;
+
+    /// Timezone of the agency, which all times are given in
+    fn timezone(&self) -> Tz;
+}
+
+/// MBTA V3 API, falling back to the offline schedule when the API cannot be reached
+pub struct MbtaProvider {
+    timezone: Tz,
+    offline_schedule: Option<GtfsIndex>,
+    catalog_file: PathBuf,
+}
+
+impl MbtaProvider {
+    pub fn new(
+        timezone: Tz,
+        offline_schedule: Option<GtfsIndex>,
+        catalog_file: PathBuf,
+    ) -> MbtaProvider {
+        MbtaProvider {
+            timezone,
+            offline_schedule,
+            catalog_file,
+        }
+    }
+}
+
+#[async_trait]
+impl TransitProvider for MbtaProvider {
+    async fn catalog(&self, update: bool) -> Result<Catalog, Box<dyn Error>> {
+        all_mbta_info(update, &self.catalog_file).await
+    }
+
+    async fn refresh_catalog(&self) -> Result<(), Box<dyn Error>> {
+        refresh_mbta_info(&self.catalog_file).await
+    }
+
+    async fn stop(&self, stop_number: &str) -> Result<Option<Stop>, Box<dyn Error>> {
+        match (find_stop(stop_number).await, &self.offline_schedule) {
+            (Err(_), Some(index)) => Ok(index.stop(stop_number)),
+            (stop, _) => stop,
+        }
+    }
+
+    async fn train_times(&self, query: &Query) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>> {
+        let live_times = train_time::train_times(
+            &query.dir_code,
+            &query.stations,
+            &query.route_codes,
+            &query.destination,
+            query.skip_full,
+            query.time_type,
+            self.timezone,
+        )
+        .await;
+        match (live_times, &self.offline_schedule) {
+            (Err(_), Some(index)) => Ok(index.train_times(
+                &query.dir_code,
+                &query.stations,
+                &query.route_codes,
+                &query.destination,
+                query.time_type,
+                self.timezone,
+            )),
+            (live_times, _) => live_times,
+        }
+    }
+
+    async fn alerts(&self, query: &Query) -> Result<Vec<Alert>, Box<dyn Error>> {
+        train_time::alerts(&query.dir_code, &query.stations, &query.route_codes).await
+    }
+
+    async fn vehicle_position(
+        &self,
+        train: &TrainTime,
+    ) -> Result<Option<VehiclePosition>, Box<dyn Error>> {
+        train_time::vehicle_position(train).await
+    }
+
+    async fn service_span(
+        &self,
+        query: &Query,
+    ) -> Result<Option<[DateTime<Tz>; 2]>, Box<dyn Error>> {
+        let live_span = train_time::max_min_times(
+            &query.dir_code,
+            &query.stations,
+            &query.route_codes,
+            query.time_type,
+            self.timezone,
+        )
+        .await;
+        match (live_span, &self.offline_schedule) {
+            (Err(_), Some(index)) => Ok(index.max_min_times(
+                &query.dir_code,
+                &query.stations,
+                &query.route_codes,
+                query.time_type,
+                self.timezone,
+            )),
+            (live_span, _) => live_span,
+        }
+    }
+
+    fn timezone(&self) -> Tz {
+        self.timezone
+    }
+}
+
+/// Any agency's GTFS static schedule with its GTFS-Realtime feeds.  The static schedule provides
+/// the stations, platforms and service span, and is used when the realtime feed cannot be reached
+pub struct GtfsProvider {
+    timezone: Tz,
+    schedule: GtfsIndex,
+    trip_updates_url: String,
+    alerts_url: Option<String>,
+    vehicle_positions_url: Option<String>,
+}
+
+impl GtfsProvider {
+    pub fn new(
+        timezone: Tz,
+        schedule: GtfsIndex,
+        trip_updates_url: String,
+        alerts_url: Option<String>,
+        vehicle_positions_url: Option<String>,
+    ) -> GtfsProvider {
+        GtfsProvider {
+            timezone,
+            schedule,
+            trip_updates_url,
+            alerts_url,
+            vehicle_positions_url,
+        }
+    }
+}
+
+#[async_trait]
+impl TransitProvider for GtfsProvider {
+    async fn catalog(&self, _update: bool) -> Result<Catalog, Box<dyn Error>> {
+        // the catalog is updated by importing a newer GTFS zip
+        Ok(self.schedule.catalog())
+    }
+
+    async fn stop(&self, stop_number: &str) -> Result<Option<Stop>, Box<dyn Error>> {
+        Ok(self.schedule.stop(stop_number))
+    }
+
+    async fn train_times(&self, query: &Query) -> Result<Option<Vec<TrainTime>>, Box<dyn Error>> {
+        // the scheduled trains, which the realtime feed updates.  The trains the feed has no
+        // predictions for, or all of them when it has none, keep their scheduled times, and
+        // without the feed the schedule is counted down to
+        let scheduled = self.schedule.train_times(
+            &query.dir_code,
+            &query.stations,
+            &query.route_codes,
+            &query.destination,
+            query.time_type,
+            self.timezone,
+        );
+        // the feed lists platforms rather than the parent station
+        let destination_ids = self.schedule.platforms(&query.destination);
+        let now = Utc::now().with_timezone(&self.timezone);
+        let scheduled_time = |trip_id: &str, stop_id: &str, time_type| {
+            self.schedule
+                .scheduled_time(trip_id, stop_id, time_type, &now)
+        };
+        let live_times = gtfs_realtime::train_times(
+            &self.trip_updates_url,
+            &query.dir_code,
+            &self.schedule.platforms(&query.stations),
+            &destination_ids,
+            &query.route_codes,
+            query.time_type,
+            scheduled.clone().unwrap_or_default(),
+            &scheduled_time,
+            self.timezone,
+        )
+        .await;
+        match live_times {
+            Ok(train_times) => Ok(train_times),
+            Err(_) => Ok(scheduled),
+        }
+    }
+
+    async fn alerts(&self, query: &Query) -> Result<Vec<Alert>, Box<dyn Error>> {
+        match &self.alerts_url {
+            Some(alerts_url) => {
+                gtfs_realtime::alerts(
+                    alerts_url,
+                    &query.dir_code,
+                    &self.schedule.platforms(&query.stations),
+                    &query.route_codes,
+                )
+                .await
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn vehicle_position(
+        &self,
+        train: &TrainTime,
+    ) -> Result<Option<VehiclePosition>, Box<dyn Error>> {
+        match &self.vehicle_positions_url {
+            Some(vehicle_positions_url) => {
+                gtfs_realtime::vehicle_position(
+                    vehicle_positions_url,
+                    &self.trip_updates_url,
+                    train,
+                    &|stop_id| self.schedule.stop_name(stop_id),
+                )
+                .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn service_span(
+        &self,
+        query: &Query,
+    ) -> Result<Option<[DateTime<Tz>; 2]>, Box<dyn Error>> {
+        Ok(self.schedule.max_min_times(
+            &query.dir_code,
+            &query.stations,
+            &query.route_codes,
+            query.time_type,
+            self.timezone,
+        ))
+    }
+
+    fn timezone(&self) -> Tz {
+        self.timezone
+    }
+}